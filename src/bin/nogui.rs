@@ -1,18 +1,64 @@
 use rendering::{Renderer, Options, scene::earth_scene};
 #[allow(unused_imports)]
-use rendering::scene::{Scene, weekend_scene, weekend_scene_bouncing, simple_scene, perlin_scene};
+use rendering::scene::{Scene, weekend_scene, weekend_scene_bouncing, simple_scene, perlin_scene, turntable_animation};
+use rendering::animation::Animation;
 
+const ASPECT: f64 = 3.0 / 2.0;
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = (WIDTH as f64 / ASPECT) as u32;
+const OPTS: Options = Options { pixel_samples: 100, ray_bounces: 20, spectral: false };
+
+// Usage: nogui [--frames <first>-<last>] [--animation <name>] [--fps <fps>] [--shutter <degrees>] [--spectral]
+// Without --frames a single still is rendered to scene.png, with it frames of the named animation,
+// the turntable by default
 fn main() {
-    const ASPECT: f64 = 3.0 / 2.0;
-    const WIDTH: u32 = 1200;
-    const HEIGHT: u32 = (WIDTH as f64 / ASPECT) as u32;
-    const OPTS: Options = Options { pixel_samples: 100, ray_bounces: 20 };
-    let scene = earth_scene(WIDTH, HEIGHT);
-    let renderer = Renderer::new(WIDTH, HEIGHT, OPTS, scene);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let spectral = args.iter().any(|a| a == "--spectral");
+
+    match arg("--frames") {
+        Some(range) => {
+            let (first, last) = range.split_once('-').expect("--frames expects a range like 1-48");
+            let first: u32 = first.parse().expect("invalid first frame");
+            let last: u32 = last.parse().expect("invalid last frame");
+
+            let name = arg("--animation").map_or("turntable", |n| n.as_str());
+            let Some(mut anim) = animation(name) else {
+                eprintln!("No animation called {}", name);
+                return;
+            };
+            if let Some(fps) = arg("--fps") {
+                anim.fps = fps.parse().expect("invalid fps");
+            }
+            if let Some(shutter) = arg("--shutter") {
+                anim.shutter_angle = shutter.parse().expect("invalid shutter angle");
+            }
+
+            for frame in first..=last {
+                let file = format!("frame_{:04}.png", frame);
+                render(anim.frame(frame), &file, spectral);
+            }
+        },
+        None => match earth_scene(WIDTH, HEIGHT) {
+            Ok(scene) => render(scene, "scene.png", spectral),
+            Err(e) => eprintln!("Couldn't load the earth texture: {}", e),
+        },
+    }
+}
+
+fn animation(name: &str) -> Option<Animation> {
+    match name {
+        "turntable" => Some(turntable_animation(WIDTH, HEIGHT)),
+        _ => None,
+    }
+}
+
+fn render(scene: Scene, file: &str, spectral: bool) {
+    let renderer = Renderer::new(WIDTH, HEIGHT, Options { spectral, ..OPTS }, scene);
 
     let mut fb: Vec<u8> = vec![0; WIDTH as usize * HEIGHT as usize * 3];
     let elapsed = renderer.cast_rays(&mut fb);
-    println!("This scene took {}ms to render.", elapsed.as_millis());
-    
-    image::save_buffer("scene.png", &fb, WIDTH, HEIGHT, image::ColorType::Rgb8).unwrap()
+    println!("{} took {}ms to render.", file, elapsed.as_millis());
+
+    image::save_buffer(file, &fb, WIDTH, HEIGHT, image::ColorType::Rgb8).unwrap()
 }
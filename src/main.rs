@@ -7,7 +7,7 @@ fn main() {
     let aspect_ratio = 3.0 / 2.0;
     let im_width: u32 = 1200; //1280;
     let im_height: u32 = (im_width as f64 / aspect_ratio) as u32; //780;
-    let options = Options { pixel_samples: 100, ray_bounces: 50 };
+    let options = Options { pixel_samples: 100, ray_bounces: 50, spectral: false };
 
     // Set up the frame buffer which we use for rendering
     let mut fb: Vec<u8> = vec![0u8; im_width as usize * im_height as usize * 3];
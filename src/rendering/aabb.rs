@@ -1,6 +1,6 @@
 use std::cmp::Ordering;
 
-use super::math::{Ray, Vec3, vec3};
+use super::math::{AnimatedTransform, Quat, Ray, Transform, Vec3, vec3};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Aabb {
@@ -24,6 +24,71 @@ impl Aabb {
         }
         true
     }
+
+    // Gives flat boxes a little thickness so rays along their plane still hit them
+    pub fn pad(&self) -> Aabb {
+        let delta = 1e-4;
+        let mut bbox = *self;
+        for a in 0..3 {
+            if bbox.max[a] - bbox.min[a] < delta {
+                bbox.min[a] -= delta / 2.0;
+                bbox.max[a] += delta / 2.0;
+            }
+        }
+        bbox
+    }
+
+    // Sums how far each axis of the box reaches once transformed (Arvo 1990), rather than
+    // transforming its corners, so the infinite sides of planes stay infinite instead of becoming
+    // NaN where they meet a zero in the matrix. Transforms are taken to be affine
+    pub fn transform(&self, t: &Transform) -> Aabb {
+        let origin = t.point(&vec3!(0.0, 0.0, 0.0));
+        let mut bbox = Aabb { min: origin, max: origin };
+        for j in 0..3 {
+            let mut axis = vec3!(0.0, 0.0, 0.0);
+            axis[j] = 1.0;
+            let column = t.vector(&axis);
+            for i in 0..3 {
+                if column[i] == 0.0 {
+                    continue;
+                }
+                let (a, b) = (column[i] * self.min[j], column[i] * self.max[j]);
+                bbox.min[i] += a.min(b);
+                bbox.max[i] += a.max(b);
+            }
+        }
+        bbox
+    }
+
+    // Encloses the box over the whole motion in [time0, time1] by sampling it densely and padding
+    // each sample by the furthest a corner can stray from its chord as the rotation sweeps
+    pub fn animate(&self, motion: &AnimatedTransform, time0: f64, time1: f64) -> Aabb {
+        const STEPS: usize = 64;
+        let mut times: Vec<f64> = (0..=STEPS).map(|s| time0 + (time1 - time0) * s as f64 / STEPS as f64).collect();
+        times.extend(motion.keys().iter().map(|k| k.time).filter(|t| *t > time0 && *t < time1));
+        times.sort_by(f64::total_cmp);
+
+        // Rotations are about the object space origin so no corner is further out than this
+        let mut corner = vec3!(0.0, 0.0, 0.0);
+        for a in 0..3 {
+            corner[a] = self.min[a].abs().max(self.max[a].abs());
+        }
+        let max_scale = |s: &Vec3| s[0].abs().max(s[1].abs()).max(s[2].abs());
+
+        let mut bbox = self.transform(&motion.at(times[0]));
+        let mut prev = motion.pose(times[0]);
+        for &t in &times[1..] {
+            let pose = motion.pose(t);
+            let radius = corner.len() * max_scale(&prev.scale).max(max_scale(&pose.scale));
+            let sagitta = radius * (1.0 - (Quat::angle_between(&prev.rotation, &pose.rotation) / 2.0).cos());
+            let pad = vec3!(sagitta, sagitta, sagitta);
+
+            let step = self.transform(&pose.to_transform());
+            bbox = surrounding_box(&bbox, &Aabb { min: step.min - pad, max: step.max + pad });
+            prev = pose;
+        }
+        bbox
+    }
 }
 
 pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
@@ -39,3 +104,71 @@ pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
 pub fn box_compare(box0: &Aabb, box1: &Aabb, axis: usize) -> Ordering {
     box0.min[axis].total_cmp(&box1.min[axis])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transformed_plane_keeps_infinite_extents() {
+        let inf = f64::INFINITY;
+        let plane = Aabb { min: vec3!(-inf, -1e-4, -inf), max: vec3!(inf, 1e-4, inf) };
+        let moved = plane.transform(&Transform::translate(vec3!(1.0, 2.0, 3.0)));
+        assert_eq!([moved.min[0], moved.max[0], moved.min[2], moved.max[2]], [-inf, inf, -inf, inf]);
+        assert!((moved.min[1] - (2.0 - 1e-4)).abs() < 1e-12 && (moved.max[1] - (2.0 + 1e-4)).abs() < 1e-12);
+
+        // Tilted out of its axis the plane spans everything
+        let tilted = plane.transform(&Transform::rotate_x(30.0));
+        for a in 0..3 {
+            assert_eq!((tilted.min[a], tilted.max[a]), (-inf, inf));
+        }
+    }
+
+    #[test]
+    fn transformed_box_encloses_its_corners() {
+        let unit = Aabb { min: vec3!(-1.0, -1.0, -1.0), max: vec3!(1.0, 1.0, 1.0) };
+        let t = Transform::scale(vec3!(2.0, 1.0, 1.0)).then(&Transform::rotate_z(45.0)).then(&Transform::translate(vec3!(0.0, 5.0, 0.0)));
+        let bbox = unit.transform(&t);
+        let reach = 3.0 / 2.0f64.sqrt();
+        let expected = [(-reach, reach), (5.0 - reach, 5.0 + reach), (-1.0, 1.0)];
+        for (a, (lo, hi)) in expected.into_iter().enumerate() {
+            assert!((bbox.min[a] - lo).abs() < 1e-9 && (bbox.max[a] - hi).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn animated_box_encloses_every_pose() {
+        use crate::math::Keyframe;
+
+        let unit = Aabb { min: vec3!(-1.0, -1.0, -1.0), max: vec3!(1.0, 1.0, 1.0) };
+        let key = |time: f64, x: f64, degrees: f64, stretch: f64| Keyframe {
+            time,
+            translation: vec3!(x, 0.0, 0.0),
+            rotation: Quat::from_axis_angle(vec3!(0.3, 1.0, 0.0), degrees),
+            scale: vec3!(1.0, stretch, 1.0),
+        };
+        let motion = AnimatedTransform::new(vec![key(0.0, 0.0, 0.0, 1.0), key(0.5, 2.0, 80.0, 2.0), key(1.0, 4.0, 170.0, 1.0)]);
+        let bbox = unit.animate(&motion, 0.0, 1.0);
+
+        // Every corner stays inside throughout, and the box is no bigger than it need be
+        let mut reach = Aabb { min: vec3!(f64::INFINITY, f64::INFINITY, f64::INFINITY), max: vec3!(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY) };
+        for step in 0..=1000 {
+            let t = motion.at(step as f64 / 1000.0);
+            for corner in 0..8 {
+                let c = t.point(&vec3!(
+                    if corner & 1 == 0 { -1.0 } else { 1.0 },
+                    if corner & 2 == 0 { -1.0 } else { 1.0 },
+                    if corner & 4 == 0 { -1.0 } else { 1.0 }
+                ));
+                for a in 0..3 {
+                    assert!(bbox.min[a] <= c[a] && c[a] <= bbox.max[a], "{:?} strays outside {:?}", c, bbox);
+                    reach.min[a] = reach.min[a].min(c[a]);
+                    reach.max[a] = reach.max[a].max(c[a]);
+                }
+            }
+        }
+        for a in 0..3 {
+            assert!(reach.min[a] - bbox.min[a] < 0.05 && bbox.max[a] - reach.max[a] < 0.05, "{:?} is loose around {:?}", bbox, reach);
+        }
+    }
+}
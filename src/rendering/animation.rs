@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use crate::{
+    bvh::{BvhTree, LinearBvh},
+    camera::Camera,
+    math::{Transform, Vec3},
+    objects::Elem,
+    scene::Scene,
+};
+
+type ObjectsAt = dyn Fn(f64, f64) -> Vec<Elem> + Send + Sync;
+type CameraAt = dyn Fn(f64, f64) -> Camera + Send + Sync;
+
+/// A sequence of frames where the camera and moving objects are built per frame from the
+/// shutter interval, while static geometry keeps a single BVH shared by every frame.
+pub struct Animation {
+    pub fps: f64,
+    // 360 degrees keeps the shutter open for the whole frame, 180 for half of it
+    pub shutter_angle: f64,
+    pub skybox_colour: Vec3,
+    statics: Option<Arc<LinearBvh>>,
+    dynamics: Box<ObjectsAt>,
+    camera: Box<CameraAt>,
+}
+
+impl Animation {
+    pub fn new<D, C>(statics: Vec<Elem>, dynamics: D, camera: C, fps: f64, shutter_angle: f64, skybox_colour: Vec3) -> Self
+    where
+        D: Fn(f64, f64) -> Vec<Elem> + Send + Sync + 'static,
+        C: Fn(f64, f64) -> Camera + Send + Sync + 'static,
+    {
+        let statics = if statics.is_empty() {
+            None
+        } else {
+            Some(Arc::new(LinearBvh::new(BvhTree::new(statics, 0.0, 0.0))))
+        };
+
+        Animation {
+            fps,
+            shutter_angle,
+            skybox_colour,
+            statics,
+            dynamics: Box::new(dynamics),
+            camera: Box::new(camera),
+        }
+    }
+
+    pub fn shutter(&self, frame: u32) -> (f64, f64) {
+        let time0 = frame as f64 / self.fps;
+        (time0, time0 + self.shutter_angle / 360.0 / self.fps)
+    }
+
+    pub fn frame(&self, frame: u32) -> Scene {
+        let (time0, time1) = self.shutter(frame);
+
+        let mut objs = (self.dynamics)(time0, time1);
+        if let Some(statics) = &self.statics {
+            objs.push(Elem::Instance { blas: statics.clone(), transform: Box::new(Transform::identity()) });
+        }
+        assert!(!objs.is_empty(), "frame {} has no objects", frame);
+
+        Scene {
+            cam: (self.camera)(time0, time1),
+            skybox_colour: self.skybox_colour,
+            bvh: LinearBvh::new(BvhTree::new(objs, time0, time1)),
+        }
+    }
+}
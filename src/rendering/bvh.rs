@@ -9,13 +9,13 @@ pub enum BvhTree {
 }
 
 impl Hittable for BvhTree {
-    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>) {
+    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>, rng: &mut Rng) {
         match self {
-            BvhTree::Leaf { obj } => obj.intersect(r, i),
+            BvhTree::Leaf { obj } => obj.intersect(r, i, rng),
             BvhTree::Node { left, right, bbox } => {
                 if bbox.hit(r, i.min, i.max) {
-                    left.intersect(r, i);
-                    right.intersect(r, i);
+                    left.intersect(r, i, rng);
+                    right.intersect(r, i, rng);
                 }
             }
         }
@@ -58,6 +58,7 @@ impl BvhTree {
     }
 }
 
+#[derive(Debug)]
 pub struct LinearBvh {
     lbvh: Vec<Result<(Aabb, usize), Elem>>,
 }
@@ -85,7 +86,7 @@ impl LinearBvh {
 }
 
 impl Hittable for LinearBvh {
-    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>) {
+    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>, rng: &mut Rng) {
         let mut ix = 0;
         while ix < self.lbvh.len() {
             let val = &self.lbvh[ix];
@@ -95,7 +96,7 @@ impl Hittable for LinearBvh {
                         ix += *offset;
                     }
                 },
-                Err(obj) => obj.intersect(r, i),
+                Err(obj) => obj.intersect(r, i, rng),
             }
             ix += 1;
         }
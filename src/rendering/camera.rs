@@ -1,17 +1,13 @@
 use fastrand::Rng;
 
-use crate::{math::{Ray, Vec3}, random_f64, vec3};
+use crate::{math::{Ray, RayDifferential, Vec3}, random_f64, vec3};
 
 pub struct Camera {
-    origin: Vec3,
-    forward: Vec3,
-    right: Vec3,
-    up: Vec3,
+    keys: Vec<CamKeyframe>,
+    views: Vec<View>,
     inv_width: f64,
     inv_height: f64,
     aspect_ratio: f64,
-    fov_scale: f64,
-    focus_dist: f64,
     aperture: f64,
     time0: f64,
     time1: f64,
@@ -25,51 +21,165 @@ pub struct CamSettings {
     pub aperture: f64,
 }
 
-impl Camera {
-    pub fn new(origin: Vec3, lookat: Vec3, cs: CamSettings, time0: f64, time1: f64) -> Self {
-        let forward = (lookat - origin).unit();
+#[derive(Debug, Clone, Copy)]
+pub struct CamKeyframe {
+    pub time: f64,
+    pub origin: Vec3,
+    pub lookat: Vec3,
+    pub vfov: f64,
+    pub focus_dist: f64,
+}
+
+// The basis of a camera pose, cached per keyframe so static cameras don't rebuild it per ray
+#[derive(Clone, Copy)]
+struct View {
+    origin: Vec3,
+    forward: Vec3,
+    right: Vec3,
+    up: Vec3,
+    fov_scale: f64,
+    focus_dist: f64,
+}
+
+impl View {
+    fn new(key: &CamKeyframe) -> Self {
+        let forward = (key.lookat - key.origin).unit();
         let right = Vec3::cross(&forward, &Vec3::new(0.0, 1.0, 0.0)).unit();
         let up = Vec3::cross(&right, &forward).unit();
 
-        let aspect_ratio = cs.view_width as f64 / cs.view_height as f64;
-        let fov_scale = (cs.vfov * std::f64::consts::FRAC_PI_2 / 180.0).tan();
-
-        Camera {
-            origin,
+        View {
+            origin: key.origin,
             forward,
             right,
             up,
+            fov_scale: (key.vfov * std::f64::consts::FRAC_PI_2 / 180.0).tan(),
+            focus_dist: key.focus_dist,
+        }
+    }
+}
+
+impl Camera {
+    pub fn new(origin: Vec3, lookat: Vec3, cs: CamSettings, time0: f64, time1: f64) -> Self {
+        let key = CamKeyframe { time: time0, origin, lookat, vfov: cs.vfov, focus_dist: cs.focus_dist };
+        Self::animated(vec![key], cs, time0, time1)
+    }
+
+    // The pose is interpolated between keyframes at each ray's time, so the vfov and focus_dist
+    // of the settings are ignored in favour of the keyframes'
+    pub fn animated(mut keys: Vec<CamKeyframe>, cs: CamSettings, time0: f64, time1: f64) -> Self {
+        assert!(!keys.is_empty(), "a camera needs at least one keyframe");
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let views = keys.iter().map(View::new).collect();
+
+        Camera {
+            keys,
+            views,
             inv_height: 1.0 / cs.view_height as f64,
             inv_width: 1.0 / cs.view_width as f64,
-            aspect_ratio,
-            fov_scale,
-            focus_dist: cs.focus_dist,
+            aspect_ratio: cs.view_width as f64 / cs.view_height as f64,
             aperture: cs.aperture,
             time0,
             time1,
         }
     }
 
+    // Times outside the keyframes hold the first or last pose
+    fn view_at(&self, time: f64) -> View {
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.views[0];
+        }
+        if next == self.keys.len() {
+            return self.views[next - 1];
+        }
+
+        let (k0, k1) = (&self.keys[next - 1], &self.keys[next]);
+        let t = (time - k0.time) / (k1.time - k0.time);
+        View::new(&CamKeyframe {
+            time,
+            origin: k0.origin + (k1.origin - k0.origin) * t,
+            lookat: k0.lookat + (k1.lookat - k0.lookat) * t,
+            vfov: k0.vfov + (k1.vfov - k0.vfov) * t,
+            focus_dist: k0.focus_dist + (k1.focus_dist - k0.focus_dist) * t,
+        })
+    }
+
     pub fn get_ray_to_pixel(&self, pix_x: u32, pix_y: u32, rng: &mut Rng) -> Ray {
+        let time = random_f64(rng, self.time0, self.time1);
+        let view = self.view_at(time);
+
         let offx = rng.f64();
         let offy = rng.f64();
         let ndc_x = (pix_x as f64 + offx) * self.inv_width;
         let ndc_y = (pix_y as f64 + offy) * self.inv_height;
 
-        let cam_x = (2.0 * ndc_x - 1.0) * self.aspect_ratio * self.fov_scale;
-        let cam_y = (1.0 - 2.0 * ndc_y) * self.fov_scale;
+        let cam_x = (2.0 * ndc_x - 1.0) * self.aspect_ratio * view.fov_scale;
+        let cam_y = (1.0 - 2.0 * ndc_y) * view.fov_scale;
 
         let rd = Vec3::random_in_unit_disk(rng) * (self.aperture / 2.0);
-        let offset = self.right * rd[0] + self.up * rd[1];
+        let offset = view.right * rd[0] + view.up * rd[1];
 
-        let dir = (self.forward + self.right * cam_x + self.up * cam_y) * self.focus_dist - offset; 
+        let dir = (view.forward + view.right * cam_x + view.up * cam_y) * view.focus_dist - offset;
+
+        // The same ray through the lens, aimed one pixel right and one pixel down
+        let pixel_x = view.right * (2.0 * self.inv_width * self.aspect_ratio * view.fov_scale * view.focus_dist);
+        let pixel_y = view.up * (-2.0 * self.inv_height * view.fov_scale * view.focus_dist);
+        let differential = RayDifferential {
+            rx_origin: view.origin + offset,
+            rx_dir: dir + pixel_x,
+            ry_origin: view.origin + offset,
+            ry_dir: dir + pixel_y,
+        };
 
-        let time = random_f64(rng, self.time0, self.time1);
         Ray {
-            origin: self.origin + offset,
+            origin: view.origin + offset,
             dir,
-            time, 
+            time,
             inv_dir: vec3!(1.0 / dir[0], 1.0 / dir[1], 1.0 / dir[2]),
+            wavelength: None,
+            differential: Some(differential),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        assert!((a - b).len() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn views_interpolate_between_keyframes_and_hold_outside_them() {
+        let key = |time: f64, x: f64, vfov: f64, focus_dist: f64| CamKeyframe {
+            time,
+            origin: vec3!(x, 0.0, 0.0),
+            lookat: vec3!(x, 0.0, -1.0),
+            vfov,
+            focus_dist,
+        };
+        let cs = CamSettings { view_height: 10, view_width: 10, vfov: 0.0, focus_dist: 0.0, aperture: 0.0 };
+        // Given out of order, as scene files may list them
+        let cam = Camera::animated(vec![key(2.0, 8.0, 40.0, 5.0), key(0.0, 0.0, 40.0, 1.0), key(1.0, 4.0, 80.0, 3.0)], cs, 0.0, 2.0);
+        let fov_scale = |vfov: f64| (vfov * std::f64::consts::FRAC_PI_2 / 180.0).tan();
+
+        let view = cam.view_at(0.25);
+        assert_close(&view.origin, &vec3!(1.0, 0.0, 0.0));
+        assert_close(&view.forward, &vec3!(0.0, 0.0, -1.0));
+        assert!((view.fov_scale - fov_scale(50.0)).abs() < 1e-12);
+        assert!((view.focus_dist - 1.5).abs() < 1e-12);
+
+        let view = cam.view_at(1.5);
+        assert_close(&view.origin, &vec3!(6.0, 0.0, 0.0));
+        assert!((view.fov_scale - fov_scale(60.0)).abs() < 1e-12);
+        assert!((view.focus_dist - 4.0).abs() < 1e-12);
+
+        // Before the first keyframe and after the last the pose holds still
+        for (time, x, focus_dist) in [(-1.0, 0.0, 1.0), (0.0, 0.0, 1.0), (2.0, 8.0, 5.0), (7.0, 8.0, 5.0)] {
+            let view = cam.view_at(time);
+            assert_close(&view.origin, &vec3!(x, 0.0, 0.0));
+            assert!((view.focus_dist - focus_dist).abs() < 1e-12);
         }
     }
 }
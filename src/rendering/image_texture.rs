@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::{ImageError, RgbImage};
+
+use crate::math::{Vec3, vec3};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    // Bilinear in the two mip levels nearest the footprint, blended between
+    Trilinear,
+}
+
+// How coordinates outside [0, 1] are brought back onto the image
+#[derive(Debug, Clone, Copy)]
+pub enum Wrap {
+    Repeat,
+    Mirror,
+    Clamp,
+    Border(Vec3),
+}
+
+/// Maps surface uvs onto the image, scaling then rotating anticlockwise by degrees about the
+/// origin then offsetting.
+#[derive(Debug, Clone, Copy)]
+pub struct UvTransform {
+    pub scale: (f64, f64),
+    pub rotation: f64,
+    pub offset: (f64, f64),
+}
+
+impl UvTransform {
+    pub fn identity() -> Self {
+        UvTransform { scale: (1.0, 1.0), rotation: 0.0, offset: (0.0, 0.0) }
+    }
+
+    // Also returns how much it stretches distances, to scale footprints by
+    fn apply(&self, u: f64, v: f64) -> (f64, f64, f64) {
+        let (su, sv) = (u * self.scale.0, v * self.scale.1);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let stretch = self.scale.0.abs().max(self.scale.1.abs());
+        (cos * su - sin * sv + self.offset.0, sin * su + cos * sv + self.offset.1, stretch)
+    }
+}
+
+// How the bytes of an image relate to the linear values rendered with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColourSpace {
+    // Colour as most image files store it, gamma encoded
+    Srgb,
+    // Data used as is, like normal maps
+    Linear,
+}
+
+// One level of a mip chain in linear floats, rows running down from the top of the image
+#[derive(Debug)]
+struct Level {
+    width: u32,
+    height: u32,
+    texels: Vec<[f32; 3]>,
+}
+
+/// An image with its chain of mip levels, each half the size of the last. The levels are shared,
+/// so clones only differ in how they're filtered, wrapped and placed.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    levels: Arc<[Level]>,
+    filter: Filter,
+    wrap: Wrap,
+    transform: UvTransform,
+}
+
+impl ImageTexture {
+    // Trilinear filtering clamped to the edges by default
+    pub fn new(img: &RgbImage, space: ColourSpace) -> Self {
+        Self::from_levels(mip_chain(img, space))
+    }
+
+    fn from_levels(levels: Arc<[Level]>) -> Self {
+        ImageTexture { levels, filter: Filter::Trilinear, wrap: Wrap::Clamp, transform: UvTransform::identity() }
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: Wrap) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: UvTransform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    // The footprint is how wide an area of uv space the lookup covers, zero for a point
+    pub fn sample(&self, u: f64, v: f64, footprint: f64) -> Vec3 {
+        let (u, v, stretch) = self.transform.apply(u, v);
+        match self.filter {
+            Filter::Nearest => self.nearest(0, u, v),
+            Filter::Bilinear => self.bilinear(0, u, v),
+            Filter::Trilinear => {
+                let base = &self.levels[0];
+                let texels = footprint * stretch * base.width.max(base.height) as f64;
+                let lod = texels.max(1.0).log2().min((self.levels.len() - 1) as f64);
+                let lo = lod.floor() as usize;
+                let hi = (lo + 1).min(self.levels.len() - 1);
+                let t = lod - lo as f64;
+                self.bilinear(lo, u, v) * (1.0 - t) + self.bilinear(hi, u, v) * t
+            },
+        }
+    }
+
+    fn nearest(&self, level: usize, u: f64, v: f64) -> Vec3 {
+        let img = &self.levels[level];
+        let x = (u * img.width as f64).floor() as i64;
+        let y = ((1.0 - v) * img.height as f64).floor() as i64;
+        self.texel(level, x, y)
+    }
+
+    // Texel centres sit half a texel in from the edges
+    fn bilinear(&self, level: usize, u: f64, v: f64) -> Vec3 {
+        let img = &self.levels[level];
+        let x = u * img.width as f64 - 0.5;
+        let y = (1.0 - v) * img.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(level, x0, y0) * (1.0 - fx) + self.texel(level, x0 + 1, y0) * fx;
+        let bottom = self.texel(level, x0, y0 + 1) * (1.0 - fx) + self.texel(level, x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vec3 {
+        let img = &self.levels[level];
+        let (w, h) = (img.width as i64, img.height as i64);
+        let (x, y) = match self.wrap {
+            Wrap::Repeat => (x.rem_euclid(w), y.rem_euclid(h)),
+            Wrap::Mirror => (mirror(x, w), mirror(y, h)),
+            Wrap::Clamp => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+            Wrap::Border(colour) => {
+                if x < 0 || x >= w || y < 0 || y >= h {
+                    return colour;
+                }
+                (x, y)
+            },
+        };
+        let t = img.texels[(y * w + x) as usize];
+        vec3!(t[0] as f64, t[1] as f64, t[2] as f64)
+    }
+}
+
+/// Images loaded from disk, each decoded once and shared by every texture made from it.
+#[derive(Debug, Default)]
+pub struct TextureCache {
+    images: HashMap<(PathBuf, ColourSpace), Arc<[Level]>>,
+}
+
+impl TextureCache {
+    pub fn load(&mut self, path: impl AsRef<Path>, space: ColourSpace) -> Result<ImageTexture, ImageError> {
+        let key = (path.as_ref().to_path_buf(), space);
+        if let Some(levels) = self.images.get(&key) {
+            return Ok(ImageTexture::from_levels(levels.clone()));
+        }
+        let levels = mip_chain(&image::open(path)?.to_rgb8(), space);
+        self.images.insert(key, levels.clone());
+        Ok(ImageTexture::from_levels(levels))
+    }
+}
+
+// Reflects the index back and forth across the image, repeating every two widths
+fn mirror(i: i64, n: i64) -> i64 {
+    let m = i.rem_euclid(2 * n);
+    if m >= n { 2 * n - 1 - m } else { m }
+}
+
+fn mip_chain(img: &RgbImage, space: ColourSpace) -> Arc<[Level]> {
+    let decode = |c: u8| {
+        let c = c as f32 / 255.0;
+        match space {
+            ColourSpace::Linear => c,
+            ColourSpace::Srgb if c <= 0.04045 => c / 12.92,
+            ColourSpace::Srgb => ((c + 0.055) / 1.055).powf(2.4),
+        }
+    };
+    let texels = img.pixels().map(|p| p.0.map(decode)).collect();
+    let mut levels = vec![Level { width: img.width(), height: img.height(), texels }];
+    while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+        levels.push(downsample(last));
+    }
+    levels.into()
+}
+
+// Averages 2x2 blocks, odd edges reuse the last row or column
+fn downsample(img: &Level) -> Level {
+    let (w, h) = ((img.width / 2).max(1), (img.height / 2).max(1));
+    let mut texels = Vec::with_capacity((w * h) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = [0.0f32; 3];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (sx, sy) = ((2 * x + dx).min(img.width - 1), (2 * y + dy).min(img.height - 1));
+                let t = img.texels[(sy * img.width + sx) as usize];
+                for c in 0..3 {
+                    sum[c] += t[c] / 4.0;
+                }
+            }
+            texels.push(sum);
+        }
+    }
+    Level { width: w, height: h, texels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Texels in linear space valued by their column, red, and row, green
+    fn grid(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8 * 10, y as u8 * 10, 0]))
+    }
+
+    #[test]
+    fn out_of_range_texels_wrap_by_mode() {
+        let tex = ImageTexture::new(&grid(4, 3), ColourSpace::Linear);
+        let column = |wrap: Wrap, x: i64| (tex.clone().with_wrap(wrap).texel(0, x, 1)[0] * 25.5).round() as i64;
+        let columns = |wrap: Wrap| (-5..9).map(|x| column(wrap, x)).collect::<Vec<_>>();
+        assert_eq!(columns(Wrap::Repeat), [3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0]);
+        assert_eq!(columns(Wrap::Mirror), [3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0]);
+        assert_eq!(columns(Wrap::Clamp), [0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3]);
+
+        let border = vec3!(0.5, 0.25, 1.0);
+        let bordered = tex.clone().with_wrap(Wrap::Border(border));
+        assert_eq!(bordered.texel(0, -1, 1).elems, border.elems);
+        assert_eq!(bordered.texel(0, 2, 3).elems, border.elems);
+        assert_eq!(bordered.texel(0, 2, 2).elems, tex.texel(0, 2, 2).elems);
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_texel() {
+        let levels = |w: u32, h: u32| mip_chain(&grid(w, h), ColourSpace::Linear).iter().map(|l| (l.width, l.height)).collect::<Vec<_>>();
+        assert_eq!(levels(8, 8), [(8, 8), (4, 4), (2, 2), (1, 1)]);
+        assert_eq!(levels(5, 3), [(5, 3), (2, 1), (1, 1)]);
+        assert_eq!(levels(1, 4), [(1, 4), (1, 2), (1, 1)]);
+        assert_eq!(levels(1, 1), [(1, 1)]);
+
+        // A power of two image averages down to its mean
+        let chain = mip_chain(&grid(4, 4), ColourSpace::Linear);
+        let last = chain.last().unwrap().texels[0];
+        assert!((last[0] - 15.0 / 255.0).abs() < 1e-6 && (last[1] - 15.0 / 255.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cache_decodes_each_image_once() {
+        let path = std::env::temp_dir().join(format!("texture_cache_{}.png", std::process::id()));
+        grid(4, 4).save(&path).unwrap();
+
+        let mut cache = TextureCache::default();
+        let first = cache.load(&path, ColourSpace::Srgb).unwrap();
+        let again = cache.load(&path, ColourSpace::Srgb).unwrap();
+        // Decoded differently, the same file is a different image
+        let linear = cache.load(&path, ColourSpace::Linear).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Arc::ptr_eq(&first.levels, &again.levels));
+        assert!(!Arc::ptr_eq(&first.levels, &linear.levels));
+        // Once cached the file isn't needed again
+        assert!(Arc::ptr_eq(&cache.load(&path, ColourSpace::Linear).unwrap().levels, &linear.levels));
+        assert!(cache.load(path.with_extension("missing.png"), ColourSpace::Srgb).is_err());
+    }
+}
@@ -1,12 +1,262 @@
+use std::sync::Arc;
+
 use fastrand::Rng;
 
-use crate::math::{Vec3, vec3};
+use crate::image_texture::ImageTexture;
+use crate::math::{Frame, Vec3, vec3};
+use crate::media::{HomogeneousMedium, PhaseFunction};
+use crate::microfacet::Ggx;
+use crate::objects::SurfacePoint;
+
+// Surfaces no rougher than this scatter as mirrors and clear glass do. Smooth conductors still
+// have this much, as `Ggx::from_roughness` keeps some to stay well behaved
+const SPECULAR_ROUGHNESS: f64 = 0.01;
 
 #[derive(Debug, Clone)]
 pub enum Material {
     Lambertian { albedo: Texture },
+    // Rough diffuse with microfacet slopes spread by sigma in radians, flatter looking than Lambertian
+    OrenNayar { albedo: Texture, sigma: Texture },
     Metal { albedo: Texture, fuzz: f64 },
-    Dielectric { ir: f64 },
+    Dielectric(Dielectric),
+    // Scattering inside participating media, redirected by the phase function
+    Medium { albedo: Texture, phase: PhaseFunction },
+    // A GGX microfacet metal with complex index of refraction eta + ik per channel
+    Conductor { eta: Vec3, k: Vec3, roughness: Ggx },
+    Principled(Box<Principled>),
+    // A clear dielectric coating over any base, like varnish or the lacquer on plastic
+    Coated { base: Box<Material>, ir: f64, roughness: f64 },
+    // Picks between two materials per hit, the mask giving the chance of the second
+    Mix { first: Box<Material>, second: Box<Material>, mask: Texture },
+    // Bends the base material's shading normal to fake fine surface detail
+    Bumped { base: Box<Material>, map: SurfaceMap },
+}
+
+impl Material {
+    pub fn interior(&self) -> Option<&HomogeneousMedium> {
+        match self {
+            Self::Dielectric(d) => d.interior.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Whether light leaves in directions that depend on its wavelength
+    pub fn wavelength_dependent(&self) -> bool {
+        match self {
+            Self::Dielectric(d) => d.dispersion.is_some() || d.film.is_some(),
+            Self::Coated { base, .. } => base.wavelength_dependent(),
+            Self::Mix { first, second, .. } => first.wavelength_dependent() || second.wavelength_dependent(),
+            Self::Bumped { base, .. } => base.wavelength_dependent(),
+            _ => false,
+        }
+    }
+
+    // Whether light only ever leaves in the mirror or refracted direction, or so close to it that
+    // the difference can't be seen
+    pub fn specular(&self) -> bool {
+        match self {
+            Self::Metal { fuzz, .. } => *fuzz == 0.0,
+            Self::Dielectric(d) => d.roughness <= SPECULAR_ROUGHNESS,
+            // Ggx::from_roughness squares the roughness
+            Self::Conductor { roughness, .. } => roughness.alpha_x.max(roughness.alpha_y) <= SPECULAR_ROUGHNESS * SPECULAR_ROUGHNESS,
+            Self::Coated { base, roughness, .. } => *roughness <= SPECULAR_ROUGHNESS && base.specular(),
+            Self::Mix { first, second, .. } => first.specular() && second.specular(),
+            Self::Bumped { base, .. } => base.specular(),
+            _ => false,
+        }
+    }
+
+    // Light wandering beneath a glossy surface, see `HomogeneousMedium::subsurface`
+    pub fn subsurface(albedo: Vec3, mean_free_path: Vec3, ir: f64, roughness: f64) -> Self {
+        let interior = Some(HomogeneousMedium::subsurface(albedo, mean_free_path));
+        Self::Dielectric(Dielectric { interior, roughness, ..Dielectric::new(ir) })
+    }
+
+    // Clear smooth glass, water and the like
+    pub fn dielectric(ir: f64) -> Self {
+        Self::Dielectric(Dielectric::new(ir))
+    }
+
+    pub fn conductor(metal: ConductorPreset, roughness: f64) -> Self {
+        let (eta, k) = metal.ior();
+        Self::Conductor { eta, k, roughness: Ggx::from_roughness(roughness) }
+    }
+
+    // Roughness along and across the surface tangent, stretching highlights like brushed metal
+    pub fn anisotropic_conductor(metal: ConductorPreset, roughness_u: f64, roughness_v: f64) -> Self {
+        let (eta, k) = metal.ior();
+        let (u, v) = (Ggx::from_roughness(roughness_u), Ggx::from_roughness(roughness_v));
+        Self::Conductor { eta, k, roughness: Ggx { alpha_x: u.alpha_x, alpha_y: v.alpha_y } }
+    }
+}
+
+// Distance across the surface bump maps take differences over
+const BUMP_STEP: f64 = 1e-3;
+
+/// Detail applied to the shading normal without changing the geometry underneath.
+#[derive(Debug, Clone)]
+pub enum SurfaceMap {
+    // Tangent space normals stored as colours, x along u, y along v and z out of the surface
+    Normal { map: Texture, strength: f64 },
+    // Heights from any scalar texture, scale being the height in world units of a value of one
+    Bump { height: Texture, scale: f64 },
+}
+
+impl SurfaceMap {
+    // Takes the hit point along with the surface there
+    pub fn apply(&self, sp: &SurfacePoint, p: &Vec3) -> SurfacePoint {
+        let (u, v) = sp.uv;
+        let normal = match self {
+            Self::Normal { map, strength } => {
+                let c = map.sample(u, v, p, sp.footprint) * 2.0 - vec3!(1.0, 1.0, 1.0);
+                let frame = Frame::new(&sp.normal, &sp.dpdu);
+                // Keep the map's y following v, whichever handedness the uv mapping has
+                let bitangent = if Vec3::dot(&frame.bitangent, &sp.dpdv) < 0.0 { -frame.bitangent } else { frame.bitangent };
+                (frame.tangent * (c[0] * strength) + bitangent * (c[1] * strength) + frame.normal * c[2]).unit()
+            },
+            Self::Bump { height, scale } => {
+                // Displace the surface along the normal and see how its tangents tilt, differencing
+                // across half a pixel where that's known so detail finer than a pixel smooths out
+                let step = |dpd: &Vec3| match sp.footprint {
+                    f if f > 0.0 => f / 2.0,
+                    _ => BUMP_STEP / dpd.len().max(1e-8),
+                };
+                let (du, dv) = (step(&sp.dpdu), step(&sp.dpdv));
+                let value = |u: f64, v: f64, p: &Vec3| height.sample(u, v, p, sp.footprint)[0];
+                let h = value(u, v, p);
+                let hu = value(u + du, v, &(p + sp.dpdu * du));
+                let hv = value(u, v + dv, &(p + sp.dpdv * dv));
+                let dpdu = sp.dpdu + sp.normal * ((hu - h) / du * scale);
+                let dpdv = sp.dpdv + sp.normal * ((hv - h) / dv * scale);
+
+                let n = Vec3::cross(&dpdu, &dpdv);
+                if n.len_sq() < 1e-20 {
+                    sp.normal
+                } else if Vec3::dot(&n, &sp.normal) < 0.0 {
+                    -n.unit()
+                } else {
+                    n.unit()
+                }
+            },
+        };
+        SurfacePoint { normal, ..*sp }
+    }
+}
+
+/// A transparent material light refracts into, optionally filled with a medium to absorb or
+/// scatter it on the way through.
+#[derive(Debug, Clone)]
+pub struct Dielectric {
+    pub ir: f64,
+    pub interior: Option<HomogeneousMedium>,
+    // Where dielectrics overlap, the one with the highest priority fills the overlap
+    pub priority: u32,
+    // Above zero frosts the surface, blurring both reflection and refraction
+    pub roughness: f64,
+    // Replaces ir by one varying with wavelength, splitting white light into colours
+    pub dispersion: Option<Dispersion>,
+    pub film: Option<ThinFilm>,
+}
+
+impl Dielectric {
+    // Smooth, empty and unprioritised, override fields with struct update syntax
+    pub fn new(ir: f64) -> Self {
+        Dielectric { ir, interior: None, priority: 0, roughness: 0.0, dispersion: None, film: None }
+    }
+}
+
+/// A Disney-style uber material, every parameter driven by a texture so glTF metallic-roughness
+/// assets map straight onto it. Scalar parameters read the red channel, use `Texture::Channel`
+/// to pick another out of a packed map.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_colour: Texture,
+    pub metallic: Texture,
+    pub roughness: Texture,
+    // Reflectance of the non-metallic base, 0.5 gives the usual 4% head on
+    pub specular: Texture,
+    pub clearcoat: Texture,
+    pub clearcoat_roughness: Texture,
+    // Colour of the grazing glow of cloth
+    pub sheen: Texture,
+    pub transmission: Texture,
+    // Index of refraction of transmissive interiors
+    pub ir: Texture,
+}
+
+impl Principled {
+    // A plain rough plastic, override fields with struct update syntax
+    pub fn new(base_colour: Texture) -> Self {
+        Principled {
+            base_colour,
+            metallic: Texture::constant(0.0),
+            roughness: Texture::constant(0.5),
+            specular: Texture::constant(0.5),
+            clearcoat: Texture::constant(0.0),
+            clearcoat_roughness: Texture::constant(0.05),
+            sheen: Texture::constant(0.0),
+            transmission: Texture::constant(0.0),
+            ir: Texture::constant(1.5),
+        }
+    }
+}
+
+// Index of refraction as a function of wavelength, which is taken in micrometres
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    // n = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Borosilicate crown glass, the usual lens and prism glass
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    // Dense flint glass, with a much stronger spread of colours
+    pub const SF11: Dispersion = Dispersion::Sellmeier {
+        b: [1.73759695, 0.313747346, 1.89878101],
+        c: [0.013188707, 0.0623068142, 155.23629],
+    };
+
+    pub fn ior(&self, wavelength_nm: f64) -> f64 {
+        let l_sq = (wavelength_nm / 1000.0).powi(2);
+        match self {
+            Self::Cauchy { a, b } => a + b / l_sq,
+            Self::Sellmeier { b, c } => (1.0 + (0..3).map(|i| b[i] * l_sq / (l_sq - c[i])).sum::<f64>()).sqrt(),
+        }
+    }
+}
+
+// A transparent coating a few hundred nanometres thick, whose interference gives soap bubbles
+// and oil slicks their colours
+#[derive(Debug, Clone, Copy)]
+pub struct ThinFilm {
+    pub ir: f64,
+    pub thickness: f64,
+}
+
+// Measured metals, with eta and k sampled at red, green and blue wavelengths
+#[derive(Debug, Clone, Copy)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    pub fn ior(&self) -> (Vec3, Vec3) {
+        match self {
+            Self::Gold => (vec3!(0.143, 0.374, 1.442), vec3!(3.983, 2.385, 1.603)),
+            Self::Copper => (vec3!(0.200, 0.924, 1.102), vec3!(3.912, 2.452, 2.142)),
+            Self::Aluminium => (vec3!(1.657, 0.880, 0.521), vec3!(9.224, 6.270, 4.837)),
+            Self::Silver => (vec3!(0.155, 0.117, 0.138), vec3!(4.828, 3.122, 2.147)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -14,53 +264,56 @@ pub enum Texture {
     Solid { colour: Vec3 },
     Checker { odd: Box<Texture>, even: Box<Texture> },
     Perlin { noise: PerlinNoise, scale: f64 },
-    Image { img: image::RgbImage },
+    Image { img: ImageTexture },
+    // One channel of another texture copied across all three
+    Channel { tex: Box<Texture>, channel: usize },
 }
 
 impl Texture {
+    pub fn constant(value: f64) -> Self {
+        Self::Solid { colour: vec3!(value, value, value) }
+    }
+
+    // The red channel, for textures driving a single parameter
+    pub fn value(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.colour(u, v, p)[0]
+    }
+
     pub fn colour(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.sample(u, v, p, 0.0)
+    }
+
+    // Footprint is the width in uv space the lookup covers, which image textures filter over
+    pub fn sample(&self, u: f64, v: f64, p: &Vec3, footprint: f64) -> Vec3 {
         match self {
             Self::Solid { colour } => *colour,
             Self::Checker { odd, even } => {
                 let sines = (10.0 * p[0]).sin() * (10.0 * p[1]).sin() * (10.0 * p[2]).sin();
                 if sines < 0.0 {
-                    odd.colour(u, v, p)
+                    odd.sample(u, v, p, footprint)
                 } else {
-                    even.colour(u, v, p)
+                    even.sample(u, v, p, footprint)
                 }
             },
             Texture::Perlin { noise, scale } => {
                 vec3!(1.0, 1.0, 1.0) * 0.5 * (1.0 + (p[2] * *scale + 10.0 * noise.turb(p, 7)).sin())
             },
-            Texture::Image { img } => {
-                let uu = u.clamp(0.0, 1.0);
-                let vv = 1.0 - v.clamp(0.0, 1.0);
-
-                let mut i = (uu * img.width() as f64) as u32;
-                let mut j = (vv * img.height() as f64) as u32;
-
-                if i >= img.width() {
-                    i = img.width() - 1;
-                }
-
-                if j >= img.height() {
-                    j = img.height() - 1;
-                }
-
-                let pix = img.get_pixel(i, j);
-
-                vec3!(pix[0] as f64, pix[1] as f64, pix[2] as f64) / 255.0
-            }, 
+            Texture::Image { img } => img.sample(u, v, footprint),
+            Texture::Channel { tex, channel } => {
+                let c = tex.sample(u, v, p, footprint)[*channel];
+                vec3!(c, c, c)
+            },
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct PerlinNoise {
-    perm_x: Vec<i32>,
-    perm_y: Vec<i32>,
-    perm_z: Vec<i32>,
-    ranvec: Vec<Vec3>,
+    // Shared so clones of a texture don't copy the tables
+    perm_x: Arc<[i32]>,
+    perm_y: Arc<[i32]>,
+    perm_z: Arc<[i32]>,
+    ranvec: Arc<[Vec3]>,
 }
 
 impl PerlinNoise {
@@ -73,10 +326,10 @@ impl PerlinNoise {
         let ranvec: Vec<Vec3> = (0..point_count).map(|_| Vec3::random_vec(-1.0, 1.0, &mut rng).unit()).collect();
 
         PerlinNoise {
-            perm_x,
-            perm_y,
-            perm_z,
-            ranvec,
+            perm_x: perm_x.into(),
+            perm_y: perm_y.into(),
+            perm_z: perm_z.into(),
+            ranvec: ranvec.into(),
         }
     }
 
@@ -143,3 +396,57 @@ impl PerlinNoise {
         ps
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glass_indices_match_the_catalogue() {
+        // Refractive indices at the Fraunhofer F, d and C lines, from Schott's data sheets
+        let lines = [486.13, 587.56, 656.27];
+        for (glass, expected) in [(Dispersion::BK7, [1.52238, 1.51680, 1.51432]), (Dispersion::SF11, [1.80645, 1.78472, 1.77596])] {
+            for (l, n) in lines.iter().zip(expected) {
+                assert!((glass.ior(*l) - n).abs() < 1e-4, "{:?} at {}nm is {} rather than {}", glass, l, glass.ior(*l), n);
+            }
+        }
+
+        // Flint spreads colours far more than crown glass, going by their Abbe numbers
+        let abbe = |g: Dispersion| (g.ior(587.56) - 1.0) / (g.ior(486.13) - g.ior(656.27));
+        assert!((abbe(Dispersion::BK7) - 64.17).abs() < 0.1);
+        assert!((abbe(Dispersion::SF11) - 25.68).abs() < 0.1);
+    }
+
+    #[test]
+    fn conductors_reflect_by_their_complex_index() {
+        use crate::microfacet::fresnel_conductor;
+
+        for metal in [ConductorPreset::Gold, ConductorPreset::Copper, ConductorPreset::Aluminium, ConductorPreset::Silver] {
+            let (eta, k) = metal.ior();
+            let head_on = fresnel_conductor(1.0, &eta, &k);
+            let grazing = fresnel_conductor(0.0, &eta, &k);
+            for c in 0..3 {
+                // At normal incidence reflectance is |(n - 1) / (n + 1)|^2 for the complex index n
+                let expected = ((eta[c] - 1.0).powi(2) + k[c] * k[c]) / ((eta[c] + 1.0).powi(2) + k[c] * k[c]);
+                assert!((head_on[c] - expected).abs() < 1e-9, "{:?} reflects {} head on rather than {}", metal, head_on[c], expected);
+                assert!((grazing[c] - 1.0).abs() < 1e-9);
+            }
+        }
+        // Gold keeps most of its red and loses much of its blue
+        let (eta, k) = ConductorPreset::Gold.ior();
+        let gold = fresnel_conductor(1.0, &eta, &k);
+        assert!(gold[0] > 0.95 && gold[2] < 0.4);
+    }
+
+    #[test]
+    fn smooth_conductors_and_coatings_are_specular() {
+        let coat = |base: Material, roughness: f64| Material::Coated { base: Box::new(base), ir: 1.5, roughness };
+        assert!(Material::conductor(ConductorPreset::Gold, 0.0).specular());
+        assert!(!Material::conductor(ConductorPreset::Gold, 0.3).specular());
+        assert!(!Material::anisotropic_conductor(ConductorPreset::Silver, 0.0, 0.3).specular());
+        assert!(coat(Material::conductor(ConductorPreset::Copper, 0.0), 0.0).specular());
+        assert!(!coat(Material::conductor(ConductorPreset::Copper, 0.0), 0.2).specular());
+        assert!(!coat(Material::Lambertian { albedo: Texture::constant(0.5) }, 0.0).specular());
+        assert!(Material::Dielectric(Dielectric { roughness: 0.005, ..Dielectric::new(1.5) }).specular());
+    }
+}
@@ -0,0 +1,34 @@
+use crate::math::{Vec3, vec3};
+
+/// An orthonormal shading basis, local coordinates have the normal along z.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Frame {
+    // The tangent is made perpendicular to the normal, falling back to any basis if they're parallel
+    pub fn new(n: &Vec3, tangent: &Vec3) -> Self {
+        let t = tangent - n * Vec3::dot(n, tangent);
+        if t.len_sq() < 1e-12 {
+            return Self::from_normal(n);
+        }
+        let tangent = t.unit();
+        Frame { tangent, bitangent: Vec3::cross(n, &tangent), normal: *n }
+    }
+
+    pub fn from_normal(n: &Vec3) -> Self {
+        let (tangent, bitangent) = Vec3::basis(n);
+        Frame { tangent, bitangent, normal: *n }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        vec3!(Vec3::dot(v, &self.tangent), Vec3::dot(v, &self.bitangent), Vec3::dot(v, &self.normal))
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        self.tangent * v[0] + self.bitangent * v[1] + self.normal * v[2]
+    }
+}
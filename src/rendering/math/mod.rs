@@ -1,5 +1,11 @@
 mod vec3;
 mod ray;
+mod transform;
+mod poly;
+mod frame;
 pub use vec3::{Vec3, vec3};
-pub use ray::Ray;
+pub use ray::{Ray, RayDifferential};
+pub use transform::{AnimatedTransform, Keyframe, Mat4, Quat, Transform};
+pub use frame::Frame;
+pub use poly::{Roots, solve_quadratic, solve_cubic, solve_quartic};
 
@@ -0,0 +1,192 @@
+// Closed form polynomial root finding after Schwarze's "Cubic and Quartic Roots" (Graphics Gems).
+// Coefficients are ordered from the constant term up.
+
+const EQN_EPS: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Roots {
+    vals: [f64; 4],
+    len: usize,
+}
+
+impl Roots {
+    fn new() -> Self {
+        Roots { vals: [0.0; 4], len: 0 }
+    }
+
+    fn push(&mut self, root: f64) {
+        self.vals[self.len] = root;
+        self.len += 1;
+    }
+
+    pub fn as_slice(&self) -> &[f64] {
+        &self.vals[..self.len]
+    }
+}
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EQN_EPS
+}
+
+pub fn solve_quadratic(c: [f64; 3]) -> Roots {
+    let mut roots = Roots::new();
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let d = p * p - q;
+
+    if is_zero(d) {
+        roots.push(-p);
+    } else if d > 0.0 {
+        let sqrt_d = d.sqrt();
+        roots.push(sqrt_d - p);
+        roots.push(-sqrt_d - p);
+    }
+    roots
+}
+
+pub fn solve_cubic(c: [f64; 4]) -> Roots {
+    let mut roots = Roots::new();
+
+    // Normal form x^3 + Ax^2 + Bx + C, then substitute x = y - A/3 to drop the quadratic term
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+
+    if is_zero(d) {
+        if is_zero(q) {
+            roots.push(0.0);
+        } else {
+            let u = (-q).cbrt();
+            roots.push(2.0 * u);
+            roots.push(-u);
+        }
+    } else if d < 0.0 {
+        // Casus irreducibilis, three real roots
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        roots.push(t * phi.cos());
+        roots.push(-t * (phi + std::f64::consts::FRAC_PI_3).cos());
+        roots.push(-t * (phi - std::f64::consts::FRAC_PI_3).cos());
+    } else {
+        let sqrt_d = d.sqrt();
+        roots.push((sqrt_d - q).cbrt() - (sqrt_d + q).cbrt());
+    }
+
+    let sub = a / 3.0;
+    for root in &mut roots.vals[..roots.len] {
+        *root -= sub;
+    }
+    roots
+}
+
+pub fn solve_quartic(c: [f64; 5]) -> Roots {
+    let mut roots = Roots::new();
+
+    // Normal form x^4 + Ax^3 + Bx^2 + Cx + D, then substitute x = y - A/4 to drop the cubic term
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+
+    if is_zero(r) {
+        // y(y^3 + py + q) = 0
+        roots.push(0.0);
+        for &root in solve_cubic([q, p, 0.0, 1.0]).as_slice() {
+            roots.push(root);
+        }
+    } else {
+        // Take one root of the resolvent cubic to split into two quadratics
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0]).as_slice()[0];
+
+        let mut u = z * z - r;
+        let mut v = 2.0 * z - p;
+        if is_zero(u) {
+            u = 0.0;
+        } else if u > 0.0 {
+            u = u.sqrt();
+        } else {
+            return roots;
+        }
+        if is_zero(v) {
+            v = 0.0;
+        } else if v > 0.0 {
+            v = v.sqrt();
+        } else {
+            return roots;
+        }
+
+        let v = if q < 0.0 { -v } else { v };
+        for &root in solve_quadratic([z - u, v, 1.0]).as_slice() {
+            roots.push(root);
+        }
+        for &root in solve_quadratic([z + u, -v, 1.0]).as_slice() {
+            roots.push(root);
+        }
+    }
+
+    let sub = a / 4.0;
+    for root in &mut roots.vals[..roots.len] {
+        *root -= sub;
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compares roots in any order against the expected ones, listing repeated roots once
+    fn assert_roots(roots: Roots, expected: &[f64]) {
+        let found = roots.as_slice();
+        for root in found {
+            assert!(expected.iter().any(|e| (root - e).abs() < 1e-6), "unexpected root {} in {:?}", root, found);
+        }
+        for e in expected {
+            assert!(found.iter().any(|root| (root - e).abs() < 1e-6), "missing root {} in {:?}", e, found);
+        }
+    }
+
+    #[test]
+    fn quadratic_and_cubic_roots() {
+        // (x - 2)(x + 3) and (x - 1)(x - 2)(x + 4)
+        assert_roots(solve_quadratic([-6.0, 1.0, 1.0]), &[2.0, -3.0]);
+        assert_roots(solve_quadratic([1.0, 0.0, 1.0]), &[]);
+        assert_roots(solve_cubic([8.0, -10.0, 1.0, 1.0]), &[1.0, 2.0, -4.0]);
+        // (x - 3)(x^2 + 1)
+        assert_roots(solve_cubic([-3.0, 1.0, -3.0, 1.0]), &[3.0]);
+    }
+
+    #[test]
+    fn quartic_with_four_real_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4), scaled to check the normal form
+        assert_roots(solve_quartic([48.0, -100.0, 70.0, -20.0, 2.0]), &[1.0, 2.0, 3.0, 4.0]);
+        // x(x - 1)(x - 2)(x + 3), with no constant term
+        assert_roots(solve_quartic([0.0, 6.0, -7.0, 0.0, 1.0]), &[0.0, 1.0, 2.0, -3.0]);
+    }
+
+    #[test]
+    fn quartic_with_repeated_roots() {
+        // (x - 1)^2 (x + 2)^2
+        assert_roots(solve_quartic([4.0, -4.0, -3.0, 2.0, 1.0]), &[1.0, -2.0]);
+        // (x - 1)^2 (x - 2)(x - 3)
+        assert_roots(solve_quartic([6.0, -17.0, 17.0, -7.0, 1.0]), &[1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn quartic_with_some_or_no_real_roots() {
+        // (x - 1)(x + 3)(x^2 + 1)
+        assert_roots(solve_quartic([-3.0, 2.0, -2.0, 2.0, 1.0]), &[1.0, -3.0]);
+        // (x^2 + 1)(x^2 + 4)
+        assert_roots(solve_quartic([4.0, 0.0, 5.0, 0.0, 1.0]), &[]);
+    }
+}
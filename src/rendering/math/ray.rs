@@ -5,6 +5,33 @@ pub struct Ray {
     pub dir: Vec3,
     pub inv_dir: Vec3,
     pub time: f64,
+    // In nanometres, once something wavelength dependent has split the ray from white light
+    pub wavelength: Option<f64>,
+    // Neighbouring rays a pixel over, followed through mirrors and glass to size texture lookups
+    pub differential: Option<RayDifferential>,
+}
+
+/// Rays offset one pixel across and one pixel down from the main ray.
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_dir: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_dir: Vec3,
+}
+
+impl RayDifferential {
+    // Where the offset rays meet the plane through p with normal n, None if they run parallel to it
+    pub fn on_plane(&self, p: &Vec3, n: &Vec3) -> Option<(Vec3, Vec3)> {
+        let hit = |origin: &Vec3, dir: &Vec3| {
+            let denom = Vec3::dot(n, dir);
+            if denom.abs() < 1e-12 {
+                return None;
+            }
+            Some(origin + dir * (Vec3::dot(n, &(p - origin)) / denom))
+        };
+        Some((hit(&self.rx_origin, &self.rx_dir)?, hit(&self.ry_origin, &self.ry_dir)?))
+    }
 }
 
 impl Ray {
@@ -12,12 +39,56 @@ impl Ray {
         self.origin += self.dir * t;
     }
 
-    pub fn reflect(&mut self, n: Vec3) {
-        self.dir -= n * Vec3::dot(&self.dir, &n) * 2.0;             
+    // Mirrors the ray in n. dn is how n turns towards where each offset ray meets the surface,
+    // which spreads or focuses the offsets on curved mirrors
+    pub fn reflect(&mut self, n: Vec3, dn: (Vec3, Vec3)) {
+        let wo = -self.dir.unit();
+        self.dir -= n * Vec3::dot(&self.dir, &n) * 2.0;
+        if let Some(d) = &mut self.differential {
+            // The change in the mirrored direction from the changes in the incoming one and the normal
+            let wi = self.dir.unit();
+            let cos_o = Vec3::dot(&wo, &n);
+            let offset = |dir: &Vec3, dndx: &Vec3| {
+                let dwo = -dir.unit() - wo;
+                let dcos = Vec3::dot(&dwo, &n) + Vec3::dot(&wo, dndx);
+                wi - dwo + (*dndx * cos_o + n * dcos) * 2.0
+            };
+            d.rx_dir = offset(&d.rx_dir, &dn.0);
+            d.ry_dir = offset(&d.ry_dir, &dn.1);
+        }
+    }
+
+    // Bends the ray through a boundary with relative index ir. dn is as for reflect
+    pub fn refract(&mut self, n: Vec3, dn: (Vec3, Vec3), ir: f64, cos_thetai: f64, cos_thetat: f64) {
+        let wo = -self.dir.unit();
+        let mu = ir * cos_thetai - cos_thetat;
+        self.dir = self.dir * ir + n * mu;
+        // Near grazing exit the offsets swing without bound, so they're lost
+        if cos_thetat < 1e-6 {
+            self.differential = None;
+        }
+        if let Some(d) = &mut self.differential {
+            // As for reflect, with the change in mu from the change in the incoming cosine
+            let wi = self.dir.unit();
+            let offset = |dir: &Vec3, dndx: &Vec3| {
+                let dwo = -dir.unit() - wo;
+                let dcos = Vec3::dot(&dwo, &n) + Vec3::dot(&wo, dndx);
+                let dmu = (ir - ir * ir * cos_thetai / cos_thetat) * dcos;
+                wi - dwo * ir + *dndx * mu + n * dmu
+            };
+            d.rx_dir = offset(&d.rx_dir, &dn.0);
+            d.ry_dir = offset(&d.ry_dir, &dn.1);
+        }
     }
 
-    pub fn refract(&mut self, n: Vec3, ir: f64, cos_thetai: f64, cos_thetat: f64) {
-        self.dir = self.dir * ir + n * (ir * cos_thetai - cos_thetat);
+    // Narrows the offset rays towards the main one, as many samples a pixel each cover less of it
+    pub fn scale_differential(&mut self, s: f64) {
+        if let Some(d) = &mut self.differential {
+            d.rx_origin = self.origin + (d.rx_origin - self.origin) * s;
+            d.ry_origin = self.origin + (d.ry_origin - self.origin) * s;
+            d.rx_dir = self.dir + (d.rx_dir - self.dir) * s;
+            d.ry_dir = self.dir + (d.ry_dir - self.dir) * s;
+        }
     }
 
     pub fn schlick(ir: f64, cos_theta: f64) -> f64 {
@@ -0,0 +1,407 @@
+use std::ops::*;
+
+use crate::math::{Ray, Vec3, vec3};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Mat4 {
+    pub elems: [[f64; 4]; 4]
+}
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        let mut elems = [[0.0; 4]; 4];
+        for (i, row) in elems.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Mat4 { elems }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut elems = [[0.0; 4]; 4];
+        for (i, row) in elems.iter_mut().enumerate() {
+            for (j, val) in row.iter_mut().enumerate() {
+                *val = self.elems[j][i];
+            }
+        }
+        Mat4 { elems }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.elems;
+        let mut inv = Self::identity().elems;
+
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= p;
+                inv[col][j] /= p;
+            }
+
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let f = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= f * a[col][j];
+                    inv[row][j] -= f * inv[col][j];
+                }
+            }
+        }
+        Some(Mat4 { elems: inv })
+    }
+
+    pub fn mul_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.elems;
+        let x = m[0][0] * p[0] + m[0][1] * p[1] + m[0][2] * p[2] + m[0][3];
+        let y = m[1][0] * p[0] + m[1][1] * p[1] + m[1][2] * p[2] + m[1][3];
+        let z = m[2][0] * p[0] + m[2][1] * p[1] + m[2][2] * p[2] + m[2][3];
+        let w = m[3][0] * p[0] + m[3][1] * p[1] + m[3][2] * p[2] + m[3][3];
+        if w == 1.0 {
+            vec3!(x, y, z)
+        } else {
+            vec3!(x, y, z) / w
+        }
+    }
+
+    pub fn mul_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.elems;
+        vec3!(m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+              m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+              m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2])
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut elems = [[0.0; 4]; 4];
+        for (i, row) in elems.iter_mut().enumerate() {
+            for (j, val) in row.iter_mut().enumerate() {
+                *val = (0..4).map(|k| self.elems[i][k] * other.elems[k][j]).sum();
+            }
+        }
+        Mat4 { elems }
+    }
+}
+
+/// An affine object-to-world transform, stored alongside its inverse so
+/// rays can be taken into object space without inverting per intersection.
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    m: Mat4,
+    inv: Mat4,
+}
+
+impl Transform {
+    pub fn new(m: Mat4) -> Option<Self> {
+        m.inverse().map(|inv| Transform { m, inv })
+    }
+
+    pub fn identity() -> Self {
+        Transform { m: Mat4::identity(), inv: Mat4::identity() }
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut m = Mat4::identity();
+        let mut inv = Mat4::identity();
+        for a in 0..3 {
+            m.elems[a][3] = offset[a];
+            inv.elems[a][3] = -offset[a];
+        }
+        Transform { m, inv }
+    }
+
+    pub fn scale(factors: Vec3) -> Self {
+        let mut m = Mat4::identity();
+        let mut inv = Mat4::identity();
+        for a in 0..3 {
+            m.elems[a][a] = factors[a];
+            inv.elems[a][a] = 1.0 / factors[a];
+        }
+        Transform { m, inv }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Self {
+        Self::rotate(vec3!(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Self {
+        Self::rotate(vec3!(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Self {
+        Self::rotate(vec3!(0.0, 0.0, 1.0), degrees)
+    }
+
+    // Rodrigues' rotation about an arbitrary axis through the origin
+    pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut m = Mat4::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                m.elems[i][j] = a[i] * a[j] * (1.0 - cos) + if i == j { cos } else { 0.0 };
+            }
+        }
+        m.elems[0][1] -= a[2] * sin;
+        m.elems[0][2] += a[1] * sin;
+        m.elems[1][0] += a[2] * sin;
+        m.elems[1][2] -= a[0] * sin;
+        m.elems[2][0] -= a[1] * sin;
+        m.elems[2][1] += a[0] * sin;
+        Transform { m, inv: m.transpose() }
+    }
+
+    /// Returns the transform that applies `self` followed by `next`.
+    pub fn then(&self, next: &Transform) -> Self {
+        Transform { m: next.m * self.m, inv: self.inv * next.inv }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Transform { m: self.inv, inv: self.m }
+    }
+
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        self.m.mul_point(p)
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.m.mul_vector(v)
+    }
+
+    // Normals transform by the inverse transpose, the result is not normalised
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        self.inv.transpose().mul_vector(n)
+    }
+
+    // The direction is left unnormalised so hit distances agree in both spaces
+    pub fn ray_to_local(&self, r: &Ray) -> Ray {
+        let dir = self.inv.mul_vector(&r.dir);
+        Ray {
+            origin: self.inv.mul_point(&r.origin),
+            dir,
+            time: r.time,
+            inv_dir: vec3!(1.0 / dir[0], 1.0 / dir[1], 1.0 / dir[2]),
+            wavelength: r.wavelength,
+            differential: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quat {
+    pub fn identity() -> Self {
+        Quat { w: 1.0, v: vec3!(0.0, 0.0, 0.0) }
+    }
+
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Self {
+        let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+        Quat { w: cos, v: axis.unit() * sin }
+    }
+
+    pub fn dot(a: &Quat, b: &Quat) -> f64 {
+        a.w * b.w + Vec3::dot(&a.v, &b.v)
+    }
+
+    pub fn normalised(&self) -> Self {
+        let len = Self::dot(self, self).sqrt();
+        Quat { w: self.w / len, v: self.v / len }
+    }
+
+    // Angle in radians of the rotation taking `a` to `b` along the shortest arc
+    pub fn angle_between(a: &Quat, b: &Quat) -> f64 {
+        2.0 * Self::dot(a, b).abs().clamp(0.0, 1.0).acos()
+    }
+
+    // Interpolates along the shortest arc, falling back to a normalised lerp when nearly parallel
+    pub fn slerp(a: &Quat, b: &Quat, t: f64) -> Self {
+        let mut cos_theta = Self::dot(a, b);
+        let mut b = *b;
+        if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            b = Quat { w: -b.w, v: -b.v };
+        }
+
+        let (wa, wb) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (((1.0 - t) * theta).sin() / sin_theta, (t * theta).sin() / sin_theta)
+        };
+        Quat { w: a.w * wa + b.w * wb, v: a.v * wa + b.v * wb }.normalised()
+    }
+
+    pub fn to_transform(&self) -> Transform {
+        let (w, x, y, z) = (self.w, self.v[0], self.v[1], self.v[2]);
+        let mut m = Mat4::identity();
+        m.elems[0][0] = 1.0 - 2.0 * (y * y + z * z);
+        m.elems[0][1] = 2.0 * (x * y - w * z);
+        m.elems[0][2] = 2.0 * (x * z + w * y);
+        m.elems[1][0] = 2.0 * (x * y + w * z);
+        m.elems[1][1] = 1.0 - 2.0 * (x * x + z * z);
+        m.elems[1][2] = 2.0 * (y * z - w * x);
+        m.elems[2][0] = 2.0 * (x * z - w * y);
+        m.elems[2][1] = 2.0 * (y * z + w * x);
+        m.elems[2][2] = 1.0 - 2.0 * (x * x + y * y);
+        Transform { m, inv: m.transpose() }
+    }
+}
+
+/// A pose at a point in time, applied as scale, then rotation, then translation.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn to_transform(&self) -> Transform {
+        Transform::scale(self.scale)
+            .then(&self.rotation.to_transform())
+            .then(&Transform::translate(self.translation))
+    }
+}
+
+/// A transform interpolated between keyframes by ray time. Rotations take the
+/// shortest arc, so spins of half a turn or more need intermediate keyframes.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    keys: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    pub fn new(mut keys: Vec<Keyframe>) -> Self {
+        assert!(!keys.is_empty(), "an animated transform needs at least one keyframe");
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        AnimatedTransform { keys }
+    }
+
+    pub fn keys(&self) -> &[Keyframe] {
+        &self.keys
+    }
+
+    // Times outside the keyframes hold the first or last pose
+    pub fn pose(&self, time: f64) -> Keyframe {
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keys[0];
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1];
+        }
+
+        let (k0, k1) = (&self.keys[next - 1], &self.keys[next]);
+        let t = (time - k0.time) / (k1.time - k0.time);
+        Keyframe {
+            time,
+            translation: k0.translation + (k1.translation - k0.translation) * t,
+            rotation: Quat::slerp(&k0.rotation, &k1.rotation, t),
+            scale: k0.scale + (k1.scale - k0.scale) * t,
+        }
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        self.pose(time).to_transform()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        assert!((a - b).len() < 1e-9, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn matrix_times_inverse_is_identity() {
+        // A zero in the first pivot forces a row swap
+        let m = Mat4 { elems: [
+            [0.0, 2.0, 1.0, 3.0],
+            [1.0, 1.0, 0.0, -2.0],
+            [4.0, 0.0, 3.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ] };
+        let product = m * m.inverse().unwrap();
+        let identity = Mat4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((product.elems[i][j] - identity.elems[i][j]).abs() < 1e-12);
+            }
+        }
+
+        let mut singular = Mat4::identity();
+        singular.elems[2][2] = 0.0;
+        assert!(singular.inverse().is_none());
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        let p = vec3!(1.0, 0.0, 0.0);
+        let shift = Transform::translate(vec3!(1.0, 0.0, 0.0));
+        let double = Transform::scale(vec3!(2.0, 2.0, 2.0));
+        assert_close(&shift.then(&double).point(&p), &vec3!(4.0, 0.0, 0.0));
+        assert_close(&double.then(&shift).point(&p), &vec3!(3.0, 0.0, 0.0));
+
+        // A quarter turn about z takes x to y before the shift
+        let turn = Transform::rotate_z(90.0).then(&shift);
+        assert_close(&turn.point(&p), &vec3!(1.0, 1.0, 0.0));
+        assert_close(&turn.inverse().point(&turn.point(&p)), &p);
+    }
+
+    #[test]
+    fn scaled_sphere_normal_is_perpendicular_to_surface() {
+        let stretch = Transform::scale(vec3!(2.0, 1.0, 1.0)).then(&Transform::rotate_y(30.0));
+        let p = vec3!(1.0, 1.0, 1.0).unit();
+        let n = stretch.normal(&p).unit();
+
+        // The stretched sphere is the ellipsoid x^2/4 + y^2 + z^2 = 1 before its turn
+        let expected = Transform::rotate_y(30.0).vector(&vec3!(p[0] / 2.0, p[1], p[2])).unit();
+        assert_close(&n, &expected);
+
+        // And stays at right angles to the surface's tangents
+        for tangent in [vec3!(-p[1], p[0], 0.0), vec3!(0.0, -p[2], p[1])] {
+            assert!(Vec3::dot(&n, &stretch.vector(&tangent)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn slerp_runs_between_its_endpoints_the_short_way() {
+        let (a, b) = (Quat::from_axis_angle(vec3!(1.0, 0.0, 0.0), 20.0), Quat::from_axis_angle(vec3!(0.0, 1.0, 0.0), 90.0));
+        let p = vec3!(0.3, -0.4, 1.2);
+        let turned = |q: Quat| q.to_transform().point(&p);
+        assert_close(&turned(Quat::slerp(&a, &b, 0.0)), &turned(a));
+        assert_close(&turned(Quat::slerp(&a, &b, 1.0)), &turned(b));
+
+        // Halfway round is half the angle from either end, whichever sign b is given in
+        let negated = Quat { w: -b.w, v: -b.v };
+        for end in [b, negated] {
+            let mid = Quat::slerp(&a, &end, 0.5);
+            let total = Quat::angle_between(&a, &b);
+            assert!((Quat::angle_between(&a, &mid) - total / 2.0).abs() < 1e-9);
+            assert!((Quat::angle_between(&mid, &b) - total / 2.0).abs() < 1e-9);
+        }
+
+        // Between almost the same rotation it still lands on the ends
+        let nearly = Quat::from_axis_angle(vec3!(1.0, 0.0, 0.0), 20.01);
+        assert_close(&turned(Quat::slerp(&a, &nearly, 1.0)), &turned(nearly));
+    }
+}
@@ -35,6 +35,13 @@ impl Vec3 {
         self / self.len()
     }
 
+    // Two unit vectors completing an orthonormal basis with the unit vector n
+    pub fn basis(n: &Vec3) -> (Vec3, Vec3) {
+        let helper = if n[0].abs() > 0.9 { vec3!(0.0, 1.0, 0.0) } else { vec3!(1.0, 0.0, 0.0) };
+        let tangent = Self::cross(&helper, n).unit();
+        (tangent, Self::cross(n, &tangent))
+    }
+
     pub fn close_to_zero(v: Vec3) -> bool {
         let err = 1e-8;
         v[0].abs() < err && v[1].abs() < err && v[2].abs() < err 
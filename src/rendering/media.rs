@@ -0,0 +1,290 @@
+use std::{f64::consts::PI, fs, io, path::Path};
+
+use fastrand::Rng;
+
+use crate::aabb::Aabb;
+use crate::materials::PerlinNoise;
+use crate::math::{Vec3, vec3};
+
+// Sample layouts of a headerless volume file, stored x fastest then y then z
+#[derive(Debug, Clone, Copy)]
+pub enum RawFormat {
+    U8,
+    F32,
+}
+
+/// A dense grid of density samples stretched over `bounds`, read with trilinear filtering.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    data: Vec<f32>,
+    max: f64,
+    bounds: Aabb,
+}
+
+impl VoxelGrid {
+    // Lookups divide by the extent of the bounds and the number of voxels along each axis, so
+    // neither may be empty
+    pub fn new(dims: [usize; 3], data: Vec<f32>, bounds: Aabb) -> io::Result<Self> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        if dims.contains(&0) {
+            return invalid(format!("a {:?} grid has no voxels", dims));
+        }
+        if (0..3).any(|a| bounds.max[a] <= bounds.min[a]) {
+            return invalid(format!("a grid can't be stretched over the flat bounds {:?}", bounds));
+        }
+        if data.len() != dims[0] * dims[1] * dims[2] {
+            return invalid(format!("{} samples don't fill a {:?} grid", data.len(), dims));
+        }
+        let max = data.iter().fold(0.0f32, |m, &d| m.max(d)) as f64;
+        Ok(VoxelGrid { dims, data, max, bounds })
+    }
+
+    // U8 samples are normalised to [0, 1], F32 samples are little endian and taken as is
+    pub fn from_raw<P: AsRef<Path>>(path: P, dims: [usize; 3], format: RawFormat, bounds: Aabb) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let count = dims[0] * dims[1] * dims[2];
+        let sample_size = match format {
+            RawFormat::U8 => 1,
+            RawFormat::F32 => 4,
+        };
+        if bytes.len() != count * sample_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {} bytes for a {:?} grid, found {}", count * sample_size, dims, bytes.len()),
+            ));
+        }
+
+        let data = match format {
+            RawFormat::U8 => bytes.iter().map(|&b| b as f32 / 255.0).collect(),
+            RawFormat::F32 => bytes.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+        };
+        Self::new(dims, data, bounds)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.data[x + self.dims[0] * (y + self.dims[1] * z)] as f64
+    }
+
+    // Samples sit at voxel centres, points outside the bounds are empty
+    pub fn lookup(&self, p: &Vec3) -> f64 {
+        let mut lo = [0usize; 3];
+        let mut hi = [0usize; 3];
+        let mut frac = [0.0; 3];
+        for a in 0..3 {
+            let rel = (p[a] - self.bounds.min[a]) / (self.bounds.max[a] - self.bounds.min[a]);
+            if !(0.0..=1.0).contains(&rel) {
+                return 0.0;
+            }
+            let g = (rel * self.dims[a] as f64 - 0.5).clamp(0.0, (self.dims[a] - 1) as f64);
+            lo[a] = g.floor() as usize;
+            hi[a] = (lo[a] + 1).min(self.dims[a] - 1);
+            frac[a] = g - lo[a] as f64;
+        }
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let x00 = lerp(self.voxel(lo[0], lo[1], lo[2]), self.voxel(hi[0], lo[1], lo[2]), frac[0]);
+        let x10 = lerp(self.voxel(lo[0], hi[1], lo[2]), self.voxel(hi[0], hi[1], lo[2]), frac[0]);
+        let x01 = lerp(self.voxel(lo[0], lo[1], hi[2]), self.voxel(hi[0], lo[1], hi[2]), frac[0]);
+        let x11 = lerp(self.voxel(lo[0], hi[1], hi[2]), self.voxel(hi[0], hi[1], hi[2]), frac[0]);
+        lerp(lerp(x00, x10, frac[1]), lerp(x01, x11, frac[1]), frac[2])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Density {
+    Grid { grid: VoxelGrid, scale: f64 },
+    // Turbulence squashed into [0, 1] and scaled, giving billowing cloud-like density
+    Perlin { noise: PerlinNoise, frequency: f64, scale: f64 },
+}
+
+impl Density {
+    pub fn at(&self, p: &Vec3) -> f64 {
+        match self {
+            Self::Grid { grid, scale } => grid.lookup(p) * scale,
+            Self::Perlin { noise, frequency, scale } => noise.turb(&(p * *frequency), 7).min(1.0) * scale,
+        }
+    }
+
+    // An upper bound on the density anywhere in the medium
+    pub fn majorant(&self) -> f64 {
+        match self {
+            Self::Grid { grid, scale } => grid.max() * scale,
+            Self::Perlin { scale, .. } => *scale,
+        }
+    }
+}
+
+/// The interior of a dielectric, with absorption and scattering coefficients per unit distance
+/// and per channel. Between scattering events light falls off following Beer-Lambert.
+#[derive(Debug, Clone, Copy)]
+pub struct HomogeneousMedium {
+    pub absorption: Vec3,
+    pub scattering: Vec3,
+    pub phase: PhaseFunction,
+}
+
+impl HomogeneousMedium {
+    /// A scattering interior from the colour it shows after many bounces and the mean distance
+    /// light travels between interactions, as for skin, marble or milk. The albedo is inverted to
+    /// each interaction's albedo with the fit of Chiang et al. for isotropic random walks.
+    pub fn subsurface(albedo: Vec3, mean_free_path: Vec3) -> Self {
+        let mut absorption = vec3!(0.0, 0.0, 0.0);
+        let mut scattering = vec3!(0.0, 0.0, 0.0);
+        for c in 0..3 {
+            let a = albedo[c].clamp(0.0, 1.0);
+            let single = 1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2);
+            let extinction = 1.0 / mean_free_path[c];
+            scattering[c] = single * extinction;
+            absorption[c] = extinction - scattering[c];
+        }
+        HomogeneousMedium { absorption, scattering, phase: PhaseFunction::Isotropic }
+    }
+
+    pub fn transmittance(&self, dist: f64) -> Vec3 {
+        let e = self.absorption + self.scattering;
+        vec3!((-e[0] * dist).exp(), (-e[1] * dist).exp(), (-e[2] * dist).exp())
+    }
+
+    // Samples how far a ray travels before scattering, using one channel's coefficient picked at
+    // random and weighting by all three. Gives the distance if it scatters within `dist`, with
+    // the weight of the path either way
+    pub fn sample_flight(&self, dist: f64, rng: &mut Rng) -> (Option<f64>, Vec3) {
+        let s = self.scattering;
+        let c = rng.usize(0..3);
+        let flight = if s[c] > 0.0 { -(1.0 - rng.f64()).ln() / s[c] } else { f64::INFINITY };
+
+        if flight < dist {
+            let pdf = (0..3).map(|i| s[i] * (-s[i] * flight).exp()).sum::<f64>() / 3.0;
+            (Some(flight), self.transmittance(flight) * s / pdf)
+        } else {
+            let pass = (0..3).map(|i| (-s[i] * dist).exp()).sum::<f64>() / 3.0;
+            (None, self.transmittance(dist) / pass)
+        }
+    }
+}
+
+/// How a medium redistributes light at a scattering event. Directions are both the way light
+/// travels, so a positive asymmetry `g` favours carrying on forwards.
+#[derive(Debug, Clone, Copy)]
+pub enum PhaseFunction {
+    Isotropic,
+    HenyeyGreenstein { g: f64 },
+    // A forward and a backward lobe blended by `weight` towards the forward one
+    DoubleHenyeyGreenstein { g_forward: f64, g_back: f64, weight: f64 },
+}
+
+impl PhaseFunction {
+    pub fn eval(&self, dir_in: &Vec3, dir_out: &Vec3) -> f64 {
+        let cos_theta = Vec3::dot(&dir_in.unit(), &dir_out.unit());
+        match *self {
+            Self::Isotropic => 1.0 / (4.0 * PI),
+            Self::HenyeyGreenstein { g } => henyey_greenstein(cos_theta, g),
+            Self::DoubleHenyeyGreenstein { g_forward, g_back, weight } => {
+                weight * henyey_greenstein(cos_theta, g_forward) + (1.0 - weight) * henyey_greenstein(cos_theta, g_back)
+            },
+        }
+    }
+
+    // Sampling is exact, so the pdf of a sampled direction is the phase function itself
+    pub fn pdf(&self, dir_in: &Vec3, dir_out: &Vec3) -> f64 {
+        self.eval(dir_in, dir_out)
+    }
+
+    pub fn sample(&self, dir_in: &Vec3, rng: &mut Rng) -> Vec3 {
+        let cos_theta = match *self {
+            Self::Isotropic => 1.0 - 2.0 * rng.f64(),
+            Self::HenyeyGreenstein { g } => sample_henyey_greenstein(g, rng.f64()),
+            Self::DoubleHenyeyGreenstein { g_forward, g_back, weight } => {
+                let g = if rng.f64() < weight { g_forward } else { g_back };
+                sample_henyey_greenstein(g, rng.f64())
+            },
+        };
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.f64();
+        let w = dir_in.unit();
+        let (u, v) = Vec3::basis(&w);
+        u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + w * cos_theta
+    }
+}
+
+fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+// Inverts the CDF of the Henyey-Greenstein distribution for the cosine to the incoming direction
+fn sample_henyey_greenstein(g: f64, xi: f64) -> f64 {
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * xi;
+    }
+    let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+    ((1.0 + g * g - sq * sq) / (2.0 * g)).clamp(-1.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voxel_grids_need_voxels_and_room() {
+        let cube = Aabb { min: vec3!(0.0, 0.0, 0.0), max: vec3!(1.0, 2.0, 1.0) };
+        assert!(VoxelGrid::new([2, 1, 2], vec![0.5; 4], cube).is_ok());
+        assert!(VoxelGrid::new([2, 0, 2], vec![], cube).is_err());
+        assert!(VoxelGrid::new([2, 1, 2], vec![0.5; 3], cube).is_err());
+        for a in 0..3 {
+            let mut flat = cube;
+            flat.max[a] = flat.min[a];
+            assert!(VoxelGrid::new([2, 1, 2], vec![0.5; 4], flat).is_err());
+            flat.max[a] = flat.min[a] - 1.0;
+            assert!(VoxelGrid::new([2, 1, 2], vec![0.5; 4], flat).is_err());
+        }
+
+        // Files are held to the same, even when their size matches
+        let path = std::env::temp_dir().join(format!("voxel_grid_{}.raw", std::process::id()));
+        fs::write(&path, [0u8, 64, 128, 255]).unwrap();
+        let loaded = VoxelGrid::from_raw(&path, [2, 1, 2], RawFormat::U8, cube);
+        let flat = VoxelGrid::from_raw(&path, [2, 1, 2], RawFormat::U8, Aabb { min: cube.min, max: vec3!(1.0, 0.0, 1.0) });
+        let empty = VoxelGrid::from_raw(&path, [0, 4, 1], RawFormat::U8, cube);
+        fs::remove_file(&path).unwrap();
+        assert!((loaded.unwrap().max() - 1.0).abs() < 1e-9);
+        assert_eq!(flat.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn phase_functions_integrate_to_one_with_mean_cosine_g() {
+        let phases = [
+            (PhaseFunction::Isotropic, 0.0),
+            (PhaseFunction::HenyeyGreenstein { g: 0.7 }, 0.7),
+            (PhaseFunction::HenyeyGreenstein { g: -0.4 }, -0.4),
+            (PhaseFunction::DoubleHenyeyGreenstein { g_forward: 0.8, g_back: -0.3, weight: 0.75 }, 0.75 * 0.8 - 0.25 * 0.3),
+        ];
+        let forward = vec3!(0.0, 0.0, 1.0);
+        let mut rng = Rng::with_seed(11);
+        for (phase, g) in phases {
+            // Phase functions only depend on the angle, so integrate over its cosine by the midpoint rule
+            const STEPS: usize = 100_000;
+            let (mut total, mut mean_cos) = (0.0, 0.0);
+            for i in 0..STEPS {
+                let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / STEPS as f64;
+                let out = vec3!((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+                let weight = phase.eval(&forward, &out) * 2.0 * PI * 2.0 / STEPS as f64;
+                total += weight;
+                mean_cos += weight * cos_theta;
+            }
+            assert!((total - 1.0).abs() < 1e-4, "{:?} integrates to {}", phase, total);
+            assert!((mean_cos - g).abs() < 1e-4, "{:?} has mean cosine {}", phase, mean_cos);
+
+            // Sampled directions average out to the same cosine
+            const SAMPLES: usize = 200_000;
+            let sampled = (0..SAMPLES).map(|_| Vec3::dot(&phase.sample(&forward, &mut rng), &forward)).sum::<f64>() / SAMPLES as f64;
+            assert!((sampled - g).abs() < 0.01, "{:?} samples a mean cosine of {}", phase, sampled);
+        }
+    }
+}
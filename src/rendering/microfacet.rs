@@ -0,0 +1,130 @@
+use std::f64::consts::PI;
+
+use crate::math::{Vec3, vec3};
+
+// All directions here are in the local shading frame, pointing away from the surface
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals, with roughness alpha_x and
+/// alpha_y along the tangent and bitangent.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    // Perceptual roughness is squared, which spreads the look of the range more evenly
+    pub fn from_roughness(roughness: f64) -> Self {
+        let alpha = (roughness * roughness).max(1e-4);
+        Ggx { alpha_x: alpha, alpha_y: alpha }
+    }
+
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        if wm[2] <= 0.0 {
+            return 0.0;
+        }
+        let x = wm[0] / self.alpha_x;
+        let y = wm[1] / self.alpha_y;
+        let len_sq = x * x + y * y + wm[2] * wm[2];
+        1.0 / (PI * self.alpha_x * self.alpha_y * len_sq * len_sq)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let ax = self.alpha_x * w[0];
+        let ay = self.alpha_y * w[1];
+        let tan_sq = (ax * ax + ay * ay) / (w[2] * w[2]);
+        ((1.0 + tan_sq).sqrt() - 1.0) / 2.0
+    }
+
+    // Smith masking of one direction
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height correlated Smith masking-shadowing of a pair of directions
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of sampling wm with `sample_visible`
+    pub fn pdf_visible(&self, wo: &Vec3, wm: &Vec3) -> f64 {
+        self.g1(wo) * Vec3::dot(wo, wm).max(0.0) * self.d(wm) / wo[2].abs()
+    }
+
+    // Samples a microfacet normal visible from wo (Heitz 2018)
+    pub fn sample_visible(&self, wo: &Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Stretch the view so the distribution becomes a hemisphere
+        let vh = vec3!(self.alpha_x * wo[0], self.alpha_y * wo[1], wo[2]).unit();
+
+        let len_sq = vh[0] * vh[0] + vh[1] * vh[1];
+        let t1 = if len_sq > 0.0 { vec3!(-vh[1], vh[0], 0.0) / len_sq.sqrt() } else { vec3!(1.0, 0.0, 0.0) };
+        let t2 = Vec3::cross(&vh, &t1);
+
+        // Sample the projected area of the visible half of the hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh[2]);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // And unstretch back to the ellipsoid
+        vec3!(self.alpha_x * nh[0], self.alpha_y * nh[1], nh[2].max(1e-6)).unit()
+    }
+}
+
+pub fn reflect(w: &Vec3, n: &Vec3) -> Vec3 {
+    n * (2.0 * Vec3::dot(w, n)) - w
+}
+
+// Refracts w through the microfacet m, with ir the ratio of indices on w's side over the far side.
+// Returns None on total internal reflection
+pub fn refract(w: &Vec3, m: &Vec3, ir: f64) -> Option<Vec3> {
+    let cos_i = Vec3::dot(w, m);
+    let sin_t_sq = ir * ir * (1.0 - cos_i * cos_i).max(0.0);
+    if sin_t_sq >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin_t_sq).sqrt();
+    Some(-w * ir + m * (ir * cos_i - cos_t))
+}
+
+// Schlick's approximation blends from the reflectance at normal incidence to one by this weight
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+// Unpolarised Fresnel reflectance of a dielectric boundary, ir as in `refract`
+pub fn fresnel_dielectric(cos_i: f64, ir: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin_t_sq = ir * ir * (1.0 - cos_i * cos_i);
+    if sin_t_sq >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t_sq).sqrt();
+    let rs = (ir * cos_i - cos_t) / (ir * cos_i + cos_t);
+    let rp = (cos_i - ir * cos_t) / (cos_i + ir * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// Unpolarised Fresnel reflectance of a conductor with complex index eta + ik, per channel
+pub fn fresnel_conductor(cos_theta: f64, eta: &Vec3, k: &Vec3) -> Vec3 {
+    let mut r = vec3!(0.0, 0.0, 0.0);
+    let cos_sq = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin_sq = 1.0 - cos_sq;
+    for c in 0..3 {
+        let (eta_sq, k_sq) = (eta[c] * eta[c], k[c] * k[c]);
+        let t0 = eta_sq - k_sq - sin_sq;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta_sq * k_sq).sqrt();
+        let t1 = a2_plus_b2 + cos_sq;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos_sq * a2_plus_b2 + sin_sq * sin_sq;
+        let t4 = t2 * sin_sq;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        r[c] = 0.5 * (rs + rp);
+    }
+    r
+}
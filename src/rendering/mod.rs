@@ -6,13 +6,24 @@ mod objects;
 mod camera;
 pub mod scene;
 mod renderer;
+pub mod animation;
+pub mod media;
+mod microfacet;
+mod spectrum;
+mod image_texture;
 
 use fastrand::Rng;
 pub use renderer::Renderer;
+// What building an `animation::Animation` by hand takes
+pub use objects::Elem;
+pub use camera::{Camera, CamKeyframe, CamSettings};
+pub use materials::{ConductorPreset, Dielectric, Material, Principled, Texture};
 
 pub struct Options {
    pub pixel_samples: u16,
    pub ray_bounces: u8,
+   // Trace wavelengths rather than RGB, slower and noisier but physically coloured
+   pub spectral: bool,
 }
 
 #[inline(always)]
@@ -1,8 +1,14 @@
-use crate::math::{Ray, Vec3, vec3};
-use super::{materials::Material, aabb::{Aabb, surrounding_box}};
+use std::sync::Arc;
+
+use std::f64::consts::PI;
+
+use fastrand::Rng;
+
+use crate::math::{AnimatedTransform, Ray, Transform, Vec3, vec3, solve_quartic};
+use super::{materials::Material, aabb::{Aabb, surrounding_box}, bvh::{BvhTree, LinearBvh}, media::Density};
 
 pub trait Hittable {
-    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>);
+    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>, rng: &mut Rng);
     fn bounding_box(&self, time0: f64, time1: f64) -> Aabb;
 }
 
@@ -10,14 +16,112 @@ pub struct Intersection<'a> {
     pub min: f64,
     pub max: f64,
     pub obj: Option<&'a Elem>,
+    // Takes the hit primitive's object space to world space when it sits inside placements
+    pub to_world: Option<Transform>,
+}
+
+impl<'a> Intersection<'a> {
+    pub fn new(min: f64, max: f64) -> Self {
+        Intersection { min, max, obj: None, to_world: None }
+    }
+
+    pub fn record(&mut self, t: f64, obj: &'a Elem) {
+        self.max = t;
+        self.obj = Some(obj);
+        self.to_world = None;
+    }
+
+    // Called by a placement after a hit inside it, so transforms compose from the innermost out
+    fn place(&mut self, transform: &Transform) {
+        self.to_world = Some(match self.to_world {
+            Some(inner) => inner.then(transform),
+            None => *transform,
+        });
+    }
+
+    // Takes a ray already moved to the hit point and returns the world space surface there
+    pub fn surface(&self, obj: &Elem, r: &Ray) -> SurfacePoint {
+        let (normal, (dpdu, dpdv), (dndu, dndv), uv) = match self.to_world {
+            Some(transform) => {
+                let local = transform.ray_to_local(r);
+                let (dpdu, dpdv) = obj.compute_dpduv(&local);
+                let (dndu, dndv) = obj.compute_dnduv(&local);
+                // The normal is renormalised once transformed, so its derivatives lose any part
+                // along it and scale with it
+                let n = transform.normal(&obj.compute_normal(&local));
+                let (len, normal) = (n.len(), n.unit());
+                let turn = |dn: &Vec3| {
+                    let dn = transform.normal(dn);
+                    (dn - normal * Vec3::dot(&dn, &normal)) / len
+                };
+                (normal, (transform.vector(&dpdu), transform.vector(&dpdv)), (turn(&dndu), turn(&dndv)), obj.compute_uv(&local))
+            },
+            None => (obj.compute_normal(r), obj.compute_dpduv(r), obj.compute_dnduv(r), obj.compute_uv(r)),
+        };
+        let footprint = r.differential
+            .and_then(|d| d.on_plane(&r.origin, &normal))
+            .map_or(0.0, |(px, py)| uv_footprint(&dpdu, &dpdv, &(px - r.origin), &(py - r.origin)));
+        SurfacePoint { normal, geometric: normal, dpdu, dpdv, dndu, dndv, uv, footprint }
+    }
+}
+
+// The uv step whose combination of dpdu and dpdv best matches an offset across the surface
+fn uv_step(dpdu: &Vec3, dpdv: &Vec3, offset: &Vec3) -> Option<(f64, f64)> {
+    let (a, b, c) = (Vec3::dot(dpdu, dpdu), Vec3::dot(dpdu, dpdv), Vec3::dot(dpdv, dpdv));
+    let det = a * c - b * b;
+    if det.abs() < 1e-20 {
+        return None;
+    }
+    let (pu, pv) = (Vec3::dot(dpdu, offset), Vec3::dot(dpdv, offset));
+    Some(((c * pu - b * pv) / det, (a * pv - b * pu) / det))
+}
+
+// How far in uv the offsets dpdx and dpdy on the surface reach, taking the larger of the two
+fn uv_footprint(dpdu: &Vec3, dpdv: &Vec3, dpdx: &Vec3, dpdy: &Vec3) -> f64 {
+    let reach = |d: &Vec3| uv_step(dpdu, dpdv, d).map_or(0.0, |(du, dv)| (du * du + dv * dv).sqrt());
+    reach(dpdx).max(reach(dpdy))
+}
+
+/// The local geometry of a hit in world space. The shading normal starts out as the geometric one
+/// and may then be perturbed by normal or bump maps.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfacePoint {
+    pub normal: Vec3,
+    pub geometric: Vec3,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // How the normal turns with u and v, zero on flat surfaces
+    pub dndu: Vec3,
+    pub dndv: Vec3,
+    pub uv: (f64, f64),
+    // The width in uv covered by a pixel here, zero when unknown
+    pub footprint: f64,
+}
+
+impl SurfacePoint {
+    // How a normal `n` turns, facing either way along the surface's, between where the main ray
+    // and its offsets meet the surface. Takes a ray at the hit whose offsets have been moved onto
+    // the surface, zero without them
+    pub fn normal_spread(&self, r: &Ray, n: &Vec3) -> (Vec3, Vec3) {
+        let zero = vec3!(0.0, 0.0, 0.0);
+        let Some(d) = r.differential else {
+            return (zero, zero);
+        };
+        let sign = if Vec3::dot(n, &self.normal) < 0.0 { -1.0 } else { 1.0 };
+        let turn = |p: &Vec3| uv_step(&self.dpdu, &self.dpdv, &(p - r.origin))
+            .map_or(zero, |(du, dv)| (self.dndu * du + self.dndv * dv) * sign);
+        (turn(&d.rx_origin), turn(&d.ry_origin))
+    }
 }
 
+/// Materials are held through an `Arc` so objects can share one. The renderer tells media apart
+/// by their material, so the surfaces of a closed solid, like a cuboid's faces, must share theirs.
 #[derive(Debug)]
 pub enum Elem {
     Sphere { 
         origin: Vec3, 
         radius: f64, 
-        mat: Material
+        mat: Arc<Material>,
     },
     MovingSphere {
         origin0: Vec3,
@@ -25,15 +129,110 @@ pub enum Elem {
         radius: f64,
         time0: f64,
         time1: f64,
-        mat: Material,
+        mat: Arc<Material>,
+    },
+    // A parallelogram spanned by u and v from the corner, facing along u x v
+    Quad {
+        corner: Vec3,
+        u: Vec3,
+        v: Vec3,
+        mat: Arc<Material>,
+    },
+    Plane {
+        point: Vec3,
+        normal: Vec3,
+        mat: Arc<Material>,
+    },
+    Disk {
+        center: Vec3,
+        normal: Vec3,
+        radius: f64,
+        mat: Arc<Material>,
+    },
+    // Cylinders, cones and tori stand along +y from their origin, use a transform to orient them
+    Cylinder {
+        origin: Vec3,
+        radius: f64,
+        height: f64,
+        mat: Arc<Material>,
+    },
+    // The base of radius `radius` sits at the origin with the apex `height` above it
+    Cone {
+        origin: Vec3,
+        radius: f64,
+        height: f64,
+        mat: Arc<Material>,
+    },
+    Torus {
+        origin: Vec3,
+        major: f64,
+        minor: f64,
+        mat: Arc<Material>,
+    },
+    // Fog or smoke filling a closed boundary, scattering with the given (isotropic) material
+    ConstantMedium {
+        boundary: Box<Elem>,
+        density: f64,
+        mat: Arc<Material>,
+    },
+    // A medium whose density varies through space, tracked against the density's majorant
+    HeterogeneousMedium {
+        boundary: Box<Elem>,
+        density: Box<Density>,
+        mat: Arc<Material>,
+    },
+    // Placements wrap other objects and are never recorded as the hit object themselves
+    Instance {
+        blas: Arc<LinearBvh>,
+        transform: Box<Transform>,
+    },
+    Transformed {
+        obj: Box<Elem>,
+        transform: Box<Transform>,
+    },
+    Animated {
+        obj: Box<Elem>,
+        motion: Box<AnimatedTransform>,
     },
 }
 
 impl Elem {
+    // An axis aligned box between two opposite corners, made of six outward facing quads
+    pub fn cuboid(a: Vec3, b: Vec3, mat: Material) -> Elem {
+        let min = vec3!(a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2]));
+        let max = vec3!(a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2]));
+        let dx = vec3!(max[0] - min[0], 0.0, 0.0);
+        let dy = vec3!(0.0, max[1] - min[1], 0.0);
+        let dz = vec3!(0.0, 0.0, max[2] - min[2]);
+
+        let mat = Arc::new(mat);
+        let sides = vec![
+            Elem::Quad { corner: vec3!(min[0], min[1], max[2]), u: dx, v: dy, mat: mat.clone() },  // front
+            Elem::Quad { corner: vec3!(max[0], min[1], max[2]), u: -dz, v: dy, mat: mat.clone() }, // right
+            Elem::Quad { corner: vec3!(max[0], min[1], min[2]), u: -dx, v: dy, mat: mat.clone() }, // back
+            Elem::Quad { corner: vec3!(min[0], min[1], min[2]), u: dz, v: dy, mat: mat.clone() },  // left
+            Elem::Quad { corner: vec3!(min[0], max[1], max[2]), u: dx, v: -dz, mat: mat.clone() }, // top
+            Elem::Quad { corner: vec3!(min[0], min[1], min[2]), u: dx, v: dz, mat },               // bottom
+        ];
+        Elem::Instance {
+            blas: Arc::new(LinearBvh::new(BvhTree::new(sides, 0.0, 0.0))),
+            transform: Box::new(Transform::identity()),
+        }
+    }
+
     pub fn get_mat(&self) -> &Material {
         match self {
             Self::Sphere { mat, .. } => mat,
             Self::MovingSphere { mat, .. } => mat,
+            Self::Quad { mat, .. } => mat,
+            Self::Plane { mat, .. } => mat,
+            Self::Disk { mat, .. } => mat,
+            Self::Cylinder { mat, .. } => mat,
+            Self::Cone { mat, .. } => mat,
+            Self::Torus { mat, .. } => mat,
+            Self::ConstantMedium { mat, .. } => mat,
+            Self::HeterogeneousMedium { mat, .. } => mat,
+            _ => unreachable!("placements are never recorded as the hit object"),
         }
     }
 
@@ -46,10 +245,150 @@ impl Elem {
                 let origin = origin0 + ((r.time - time0) / (time1 - time0)) * (origin1 - origin0);
                 (r.origin - origin) / radius
             },
+            Self::Quad { u, v, .. } => Vec3::cross(&u, &v).unit(),
+            Self::Plane { normal, .. } | Self::Disk { normal, .. } => normal.unit(),
+            Self::Cylinder { origin, radius, height, .. } => {
+                let p = r.origin - origin;
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                // Whichever surface the hit point lies closest to is the one we hit
+                if p[1].abs().min((p[1] - height).abs()) < (rho - radius).abs() {
+                    vec3!(0.0, if p[1] * 2.0 < height { -1.0 } else { 1.0 }, 0.0)
+                } else {
+                    vec3!(p[0], 0.0, p[2]) / rho
+                }
+            },
+            Self::Cone { origin, radius, height, .. } => {
+                let p = r.origin - origin;
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                let k = radius / height;
+                if p[1].abs() < (rho - k * (height - p[1])).abs() {
+                    vec3!(0.0, -1.0, 0.0)
+                } else {
+                    vec3!(p[0], k * k * (height - p[1]), p[2]).unit()
+                }
+            },
+            Self::Torus { origin, major, minor, .. } => {
+                let p = r.origin - origin;
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                (p - vec3!(p[0], 0.0, p[2]) * (major / rho)) / minor
+            },
+            // Scattering inside a volume has no surface, so any unit vector will do
+            Self::ConstantMedium { .. } | Self::HeterogeneousMedium { .. } => vec3!(1.0, 0.0, 0.0),
+            _ => unreachable!("placements are never recorded as the hit object"),
+        }
+    }
+
+    // How the hit point moves with u and v, takes a ray already moved to the hit point
+    pub fn compute_dpduv(&self, r: &Ray) -> (Vec3, Vec3) {
+        let n = self.compute_normal(r);
+        match *self {
+            Self::Sphere { radius, .. } | Self::MovingSphere { radius, .. } => {
+                // Matches the latitude and longitude mapping of compute_uv
+                let sin_theta = (1.0 - n[1] * n[1]).max(1e-8).sqrt();
+                let dpdu = vec3!(n[2], 0.0, -n[0]) * (2.0 * PI * radius);
+                let dpdv = vec3!(-n[1] * n[0] / sin_theta, sin_theta, -n[1] * n[2] / sin_theta) * (PI * radius);
+                (dpdu, dpdv)
+            },
+            Self::Quad { u, v, .. } => (u, v),
+            Self::Plane { normal, .. } => Vec3::basis(&normal.unit()),
+            Self::Disk { center, normal, radius, .. } => {
+                let (tangent, bitangent) = Vec3::basis(&normal.unit());
+                let p = r.origin - center;
+                let (x, y) = (Vec3::dot(&p, &tangent), Vec3::dot(&p, &bitangent));
+                let dpdu = (bitangent * x - tangent * y) * (2.0 * PI);
+                let rho = (x * x + y * y).sqrt();
+                let dpdv = if rho > 0.0 { (tangent * x + bitangent * y) * (radius / rho) } else { tangent * radius };
+                (dpdu, dpdv)
+            },
+            Self::Cylinder { origin, radius, height, .. } | Self::Cone { origin, radius, height, .. } => {
+                let p = r.origin - origin;
+                if n[0] == 0.0 && n[2] == 0.0 {
+                    return (vec3!(2.0 * radius, 0.0, 0.0), vec3!(0.0, 0.0, 2.0 * radius));
+                }
+                let dpdu = vec3!(-p[2], 0.0, p[0]) * (2.0 * PI);
+                let dpdv = match self {
+                    Self::Cone { .. } => {
+                        // Up the slant, drawing in towards the apex
+                        let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                        let k = radius / height;
+                        vec3!(-k * p[0] / rho, 1.0, -k * p[2] / rho) * height
+                    },
+                    _ => vec3!(0.0, height, 0.0),
+                };
+                (dpdu, dpdv)
+            },
+            Self::Torus { origin, major, .. } => {
+                let p = r.origin - origin;
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                let radial = vec3!(p[0], 0.0, p[2]) / rho;
+                let dpdu = vec3!(-p[2], 0.0, p[0]) * (2.0 * PI);
+                let dpdv = (radial * -p[1] + vec3!(0.0, rho - major, 0.0)) * (2.0 * PI);
+                (dpdu, dpdv)
+            },
+            _ => Vec3::basis(&n),
         }
     }
 
-    pub fn compute_uv(&self, n: &Vec3) -> (f64, f64) {
+    // How the normal turns with u and v, matching compute_dpduv. Takes a ray already moved to the hit point
+    pub fn compute_dnduv(&self, r: &Ray) -> (Vec3, Vec3) {
+        let zero = vec3!(0.0, 0.0, 0.0);
+        let n = self.compute_normal(r);
+        let (dpdu, dpdv) = self.compute_dpduv(r);
+        match *self {
+            // The normal is the offset from the centre scaled down by the radius
+            Self::Sphere { radius, .. } | Self::MovingSphere { radius, .. } => (dpdu / radius, dpdv / radius),
+            // Caps are flat, the sides only turn around the axis
+            Self::Cylinder { radius, .. } if n[0] != 0.0 || n[2] != 0.0 => (dpdu / radius, zero),
+            Self::Cone { origin, radius, height, .. } if n[0] != 0.0 || n[2] != 0.0 => {
+                let p = r.origin - origin;
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                let k = radius / height;
+                (dpdu / (rho * (1.0 + k * k).sqrt()), zero)
+            },
+            // Around the tube the normal turns with the surface, around the axis it also turns
+            // with the circle at the tube's centre
+            Self::Torus { origin, major, minor, .. } => {
+                let p = r.origin - origin;
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                (dpdu * ((1.0 - major / rho) / minor), dpdv / minor)
+            },
+            _ => (zero, zero),
+        }
+    }
+
+    // Takes a ray already moved to the hit point
+    pub fn compute_uv(&self, r: &Ray) -> (f64, f64) {
+        match *self {
+            Self::Quad { corner, u, v, .. } => return quad_coords(&(r.origin - corner), &u, &v),
+            Self::Plane { point, normal, .. } => {
+                let (tangent, bitangent) = Vec3::basis(&normal.unit());
+                let p = r.origin - point;
+                return (Vec3::dot(&p, &tangent), Vec3::dot(&p, &bitangent));
+            },
+            Self::Disk { center, normal, radius, .. } => {
+                let (tangent, bitangent) = Vec3::basis(&normal.unit());
+                let p = r.origin - center;
+                return (azimuth(Vec3::dot(&p, &tangent), Vec3::dot(&p, &bitangent)), p.len() / radius);
+            },
+            Self::Cylinder { origin, radius, height, .. } | Self::Cone { origin, radius, height, .. } => {
+                // Caps map the disk onto the unit square, sides wrap around the axis
+                let p = r.origin - origin;
+                let n = self.compute_normal(r);
+                if n[0] == 0.0 && n[2] == 0.0 {
+                    return ((p[0] / radius + 1.0) / 2.0, (p[2] / radius + 1.0) / 2.0);
+                }
+                return (azimuth(p[0], p[2]), p[1] / height);
+            },
+            Self::Torus { origin, major, .. } => {
+                let p = r.origin - origin;
+                let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                return (azimuth(p[0], p[2]), azimuth(rho - major, p[1]));
+            },
+            Self::ConstantMedium { .. } | Self::HeterogeneousMedium { .. } => return (0.0, 0.0),
+            _ => (),
+        }
+
+        let n = self.compute_normal(r);
         //let u = n[0].atan2(n[2]) / (2.0 * std::f64::consts::PI) + 0.5;
         //let v = n[1] * 0.5 + 0.5;
         let theta = (-n[1]).acos();
@@ -62,7 +401,7 @@ impl Elem {
 }
 
 impl Hittable for Elem {
-    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>) {
+    fn intersect<'a>(&'a self, r: &mut Ray, i: &mut Intersection<'a>, rng: &mut Rng) {
         match self {
             Self::Sphere { origin, radius, mat: _ } => {
                 let diff = r.origin - origin;
@@ -85,8 +424,7 @@ impl Hittable for Elem {
                         return;
                     }
                 }
-                i.max = root;
-                i.obj = Some(self);
+                i.record(root, self);
             },
             Self::MovingSphere { origin0, origin1, radius, time0, time1, mat: _ } => {
                 let origin = origin0 + ((r.time - time0) / (time1 - time0)) * (origin1 - origin0);
@@ -110,8 +448,182 @@ impl Hittable for Elem {
                         return;
                     }
                 }
-                i.max = root;
-                i.obj = Some(self);
+                i.record(root, self);
+            },
+            Self::Quad { corner, u, v, mat: _ } => {
+                let normal = Vec3::cross(u, v).unit();
+                if let Some(t) = plane_hit(r, i, corner, &normal) {
+                    let (alpha, beta) = quad_coords(&(r.origin + r.dir * t - corner), u, v);
+                    if (0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta) {
+                        i.record(t, self);
+                    }
+                }
+            },
+            Self::Plane { point, normal, mat: _ } => {
+                if let Some(t) = plane_hit(r, i, point, &normal.unit()) {
+                    i.record(t, self);
+                }
+            },
+            Self::Disk { center, normal, radius, mat: _ } => {
+                if let Some(t) = plane_hit(r, i, center, &normal.unit()) {
+                    if (r.origin + r.dir * t - center).len_sq() <= radius * radius {
+                        i.record(t, self);
+                    }
+                }
+            },
+            Self::Cylinder { origin, radius, height, mat: _ } => {
+                let p = r.origin - origin;
+                let d = r.dir;
+                let mut best = None;
+
+                let a = d[0] * d[0] + d[2] * d[2];
+                let b_half = p[0] * d[0] + p[2] * d[2];
+                let c = p[0] * p[0] + p[2] * p[2] - radius * radius;
+                let discriminant = b_half * b_half - a * c;
+                if a > 0.0 && discriminant >= 0.0 {
+                    let sqrtd = discriminant.sqrt();
+                    for t in [(-b_half - sqrtd) / a, (-b_half + sqrtd) / a] {
+                        let y = p[1] + t * d[1];
+                        if (0.0..=*height).contains(&y) {
+                            nearer(&mut best, t, i);
+                        }
+                    }
+                }
+                for y in [0.0, *height] {
+                    let t = (y - p[1]) / d[1];
+                    let (x, z) = (p[0] + t * d[0], p[2] + t * d[2]);
+                    if x * x + z * z <= radius * radius {
+                        nearer(&mut best, t, i);
+                    }
+                }
+
+                if let Some(t) = best {
+                    i.record(t, self);
+                }
+            },
+            Self::Cone { origin, radius, height, mat: _ } => {
+                let p = r.origin - origin;
+                let d = r.dir;
+                let mut best = None;
+
+                // x^2 + z^2 = k^2 (h - y)^2 along the side
+                let k2 = (radius / height) * (radius / height);
+                let rise = height - p[1];
+                let a = d[0] * d[0] + d[2] * d[2] - k2 * d[1] * d[1];
+                let b_half = p[0] * d[0] + p[2] * d[2] + k2 * rise * d[1];
+                let c = p[0] * p[0] + p[2] * p[2] - k2 * rise * rise;
+                let side = if a.abs() < 1e-12 {
+                    [-c / (2.0 * b_half), f64::NAN]
+                } else {
+                    let discriminant = b_half * b_half - a * c;
+                    let sqrtd = discriminant.sqrt();
+                    [(-b_half - sqrtd) / a, (-b_half + sqrtd) / a]
+                };
+                for t in side {
+                    let y = p[1] + t * d[1];
+                    if (0.0..=*height).contains(&y) {
+                        nearer(&mut best, t, i);
+                    }
+                }
+
+                let t = -p[1] / d[1];
+                let (x, z) = (p[0] + t * d[0], p[2] + t * d[2]);
+                if x * x + z * z <= radius * radius {
+                    nearer(&mut best, t, i);
+                }
+
+                if let Some(t) = best {
+                    i.record(t, self);
+                }
+            },
+            Self::Torus { origin, major, minor, mat: _ } => {
+                // Solve with a unit direction from the point nearest the centre to keep the
+                // quartic's coefficients well scaled, then map back to the ray's parameter
+                let scale = r.dir.len();
+                let d = r.dir / scale;
+                let shift = -Vec3::dot(&(r.origin - origin), &d);
+                let p = r.origin - origin + d * shift;
+
+                let (r2, m2) = (minor * minor, major * major);
+                let pd = Vec3::dot(&p, &d);
+                let k = p.len_sq() - r2 - m2;
+                let coeffs = [
+                    k * k - 4.0 * m2 * (r2 - p[1] * p[1]),
+                    4.0 * pd * k + 8.0 * m2 * p[1] * d[1],
+                    2.0 * k + 4.0 * pd * pd + 4.0 * m2 * d[1] * d[1],
+                    4.0 * pd,
+                    1.0,
+                ];
+
+                let mut best = None;
+                for &s in solve_quartic(coeffs).as_slice() {
+                    nearer(&mut best, (s + shift) / scale, i);
+                }
+                if let Some(t) = best {
+                    i.record(t, self);
+                }
+            },
+            Self::ConstantMedium { boundary, density, mat: _ } => {
+                let Some((t_enter, t_exit)) = medium_span(boundary, r, i, rng) else {
+                    return;
+                };
+
+                // Sample a free flight distance, passing straight through if it overshoots the exit
+                let speed = r.dir.len();
+                let flight = -(1.0 - rng.f64()).ln() / density;
+                if flight > (t_exit - t_enter) * speed {
+                    return;
+                }
+                i.record(t_enter + flight / speed, self);
+            },
+            Self::HeterogeneousMedium { boundary, density, mat: _ } => {
+                let Some((t_enter, t_exit)) = medium_span(boundary, r, i, rng) else {
+                    return;
+                };
+                let majorant = density.majorant();
+                if majorant <= 0.0 {
+                    return;
+                }
+
+                // Delta tracking, fly against the majorant and accept a real collision with
+                // probability density / majorant, otherwise carry on through the null collision
+                let speed = r.dir.len();
+                let mut t = t_enter;
+                loop {
+                    t += -(1.0 - rng.f64()).ln() / (majorant * speed);
+                    if t >= t_exit {
+                        return;
+                    }
+                    if density.at(&(r.origin + r.dir * t)) > majorant * rng.f64() {
+                        i.record(t, self);
+                        return;
+                    }
+                }
+            },
+            Self::Instance { blas, transform } => {
+                let mut local = transform.ray_to_local(r);
+                let closest = i.max;
+                blas.intersect(&mut local, i, rng);
+                if i.max < closest {
+                    i.place(transform);
+                }
+            },
+            Self::Transformed { obj, transform } => {
+                let mut local = transform.ray_to_local(r);
+                let closest = i.max;
+                obj.intersect(&mut local, i, rng);
+                if i.max < closest {
+                    i.place(transform);
+                }
+            },
+            Self::Animated { obj, motion } => {
+                let transform = motion.at(r.time);
+                let mut local = transform.ray_to_local(r);
+                let closest = i.max;
+                obj.intersect(&mut local, i, rng);
+                if i.max < closest {
+                    i.place(&transform);
+                }
             },
         }
     }
@@ -139,8 +651,255 @@ impl Hittable for Elem {
 
                 surrounding_box(&box1, &box2)
             },
+            Self::Quad { corner, u, v, mat: _ } => {
+                let diag1 = surrounding_box(&Aabb { min: corner, max: corner }, &Aabb { min: corner + u + v, max: corner + u + v });
+                let diag2 = surrounding_box(&Aabb { min: corner + u, max: corner + u }, &Aabb { min: corner + v, max: corner + v });
+                surrounding_box(&diag1, &diag2).pad()
+            },
+            Self::Plane { point, normal, mat: _ } => {
+                // Only a plane facing along an axis has any finite extent
+                let mut bbox = Aabb {
+                    min: vec3!(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+                    max: vec3!(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                };
+                let axes: Vec<usize> = (0..3).filter(|&a| normal[a] != 0.0).collect();
+                if let [axis] = axes[..] {
+                    bbox.min[axis] = point[axis];
+                    bbox.max[axis] = point[axis];
+                }
+                bbox.pad()
+            },
+            Self::Disk { center, normal, radius, mat: _ } => {
+                // A tilted disk reaches out along each axis by the radius times the sine to the normal
+                let n = normal.unit();
+                let mut extent = vec3!(0.0, 0.0, 0.0);
+                for a in 0..3 {
+                    extent[a] = radius * (1.0 - n[a] * n[a]).max(0.0).sqrt();
+                }
+                Aabb { min: center - extent, max: center + extent }.pad()
+            },
+            Self::Cylinder { origin, radius, height, mat: _ } | Self::Cone { origin, radius, height, mat: _ } => {
+                Aabb {
+                    min: origin - vec3!(radius, 0.0, radius),
+                    max: origin + vec3!(radius, height, radius),
+                }
+            },
+            Self::Torus { origin, major, minor, mat: _ } => {
+                let reach = vec3!(major + minor, minor, major + minor);
+                Aabb { min: origin - reach, max: origin + reach }
+            },
+            Self::ConstantMedium { ref boundary, .. } | Self::HeterogeneousMedium { ref boundary, .. } => {
+                boundary.bounding_box(t0, t1)
+            },
+            Self::Instance { ref blas, ref transform } => {
+                blas.bounding_box(t0, t1).transform(transform)
+            },
+            Self::Transformed { ref obj, ref transform } => {
+                obj.bounding_box(t0, t1).transform(transform)
+            },
+            Self::Animated { ref obj, ref motion } => {
+                obj.bounding_box(t0, t1).animate(motion, t0, t1)
+            },
         }
     }
 
 }
 
+
+// Returns the distance along the ray to the plane through the point, if in the intersection's range
+fn plane_hit(r: &Ray, i: &Intersection, point: &Vec3, normal: &Vec3) -> Option<f64> {
+    let denom = Vec3::dot(normal, &r.dir);
+    if denom.abs() < 1e-8 {
+        return None;
+    }
+
+    let t = Vec3::dot(normal, &(point - r.origin)) / denom;
+    if t < i.min || t > i.max {
+        return None;
+    }
+    Some(t)
+}
+
+// Where the ray enters and leaves a medium's boundary within the intersection's range,
+// allowing for rays that start inside
+fn medium_span(boundary: &Elem, r: &mut Ray, i: &Intersection, rng: &mut Rng) -> Option<(f64, f64)> {
+    let mut enter = Intersection::new(f64::NEG_INFINITY, f64::INFINITY);
+    boundary.intersect(r, &mut enter, rng);
+    enter.obj?;
+    let mut exit = Intersection::new(enter.max + 0.0001, f64::INFINITY);
+    boundary.intersect(r, &mut exit, rng);
+    exit.obj?;
+
+    let t_enter = enter.max.max(i.min);
+    let t_exit = exit.max.min(i.max);
+    if t_enter >= t_exit {
+        return None;
+    }
+    Some((t_enter, t_exit))
+}
+
+// Keeps the nearest candidate distance that lies within the intersection's range
+fn nearer(best: &mut Option<f64>, t: f64, i: &Intersection) {
+    if t >= i.min && t <= i.max && best.is_none_or(|b| t < b) {
+        *best = Some(t);
+    }
+}
+
+// Angle around an axis as a fraction of a full turn
+fn azimuth(x: f64, y: f64) -> f64 {
+    let phi = y.atan2(x);
+    (if phi < 0.0 { phi + 2.0 * PI } else { phi }) / (2.0 * PI)
+}
+
+// Coordinates of a point relative to the corner in the basis of the quad's edges
+fn quad_coords(p: &Vec3, u: &Vec3, v: &Vec3) -> (f64, f64) {
+    let n = Vec3::cross(u, v);
+    let w = n / Vec3::dot(&n, &n);
+    (Vec3::dot(&w, &Vec3::cross(p, v)), Vec3::dot(&w, &Vec3::cross(u, p)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Texture;
+    use crate::media::{PhaseFunction, VoxelGrid};
+
+    // Distance along the ray to its nearest hit on the object, in units of the ray's direction
+    fn nearest_hit(obj: &Elem, origin: Vec3, dir: Vec3) -> Option<f64> {
+        let mut r = Ray { origin, dir, inv_dir: vec3!(1.0 / dir[0], 1.0 / dir[1], 1.0 / dir[2]), time: 0.0, wavelength: None, differential: None };
+        let mut i = Intersection::new(0.001, f64::INFINITY);
+        obj.intersect(&mut r, &mut i, &mut Rng::with_seed(0));
+        i.obj.map(|_| i.max)
+    }
+
+    fn torus(origin: Vec3) -> Elem {
+        Elem::Torus { origin, major: 2.0, minor: 0.5, mat: Arc::new(Material::dielectric(1.5)) }
+    }
+
+    fn assert_near(t: Option<f64>, expected: f64) {
+        let t = t.expect("expected a hit");
+        assert!((t - expected).abs() < 1e-6, "hit at {} rather than {}", t, expected);
+    }
+
+    #[test]
+    fn ray_through_torus_hits_nearest_side_of_tube() {
+        let t = torus(vec3!(0.0, 0.0, 0.0));
+        // Across the middle the tube spans 1.5 to 2.5 from the centre on either side
+        assert_near(nearest_hit(&t, vec3!(-5.0, 0.0, 0.0), vec3!(1.0, 0.0, 0.0)), 2.5);
+        assert_near(nearest_hit(&t, vec3!(0.0, 0.0, 0.0), vec3!(1.0, 0.0, 0.0)), 1.5);
+        // From inside the tube, out through its inner wall
+        assert_near(nearest_hit(&t, vec3!(-2.0, 0.0, 0.0), vec3!(1.0, 0.0, 0.0)), 0.5);
+        // Distances are in units of an unnormalised direction
+        assert_near(nearest_hit(&t, vec3!(-5.0, 0.0, 0.0), vec3!(2.0, 0.0, 0.0)), 1.25);
+    }
+
+    #[test]
+    fn ray_through_torus_off_centre() {
+        let t = torus(vec3!(1.0, 2.0, 3.0));
+        // 0.3 above the tube's centre line its cross section is 0.4 wide either side
+        assert_near(nearest_hit(&t, vec3!(-4.0, 2.3, 3.0), vec3!(1.0, 0.0, 0.0)), 2.6);
+        // Down the axis through the hole, and passing over the top
+        assert!(nearest_hit(&t, vec3!(1.0, 10.0, 3.0), vec3!(0.0, -1.0, 0.0)).is_none());
+        assert!(nearest_hit(&t, vec3!(-4.0, 2.6, 3.0), vec3!(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn scaled_sphere_surface_normal() {
+        let sphere = Elem::Sphere { origin: vec3!(0.0, 0.0, 0.0), radius: 1.0, mat: Arc::new(Material::dielectric(1.5)) };
+        let ellipsoid = Elem::Transformed { obj: Box::new(sphere), transform: Box::new(Transform::scale(vec3!(2.0, 1.0, 1.0))) };
+        let dir = vec3!(-1.0, -1.0, 0.0);
+        let mut r = Ray { origin: vec3!(3.0, 3.0, 0.5), dir, inv_dir: vec3!(-1.0, -1.0, f64::INFINITY), time: 0.0, wavelength: None, differential: None };
+        let mut i = Intersection::new(0.001, f64::INFINITY);
+        ellipsoid.intersect(&mut r, &mut i, &mut Rng::with_seed(0));
+        let obj = i.obj.expect("expected a hit");
+        r.move_along(i.max);
+        let sp = i.surface(obj, &r);
+
+        // The hit lies on x^2/4 + y^2 + z^2 = 1, whose normal is the gradient there
+        let q = r.origin;
+        assert!((q[0] * q[0] / 4.0 + q[1] * q[1] + q[2] * q[2] - 1.0).abs() < 1e-9);
+        let expected = vec3!(q[0] / 4.0, q[1], q[2]).unit();
+        assert!((sp.normal - expected).len() < 1e-9, "{:?} is not {:?}", sp.normal, expected);
+    }
+
+    #[test]
+    fn instances_share_one_blas_under_their_own_transforms() {
+        let Elem::Instance { blas, .. } = Elem::cuboid(vec3!(-1.0, -1.0, -1.0), vec3!(1.0, 1.0, 1.0), Material::dielectric(1.5)) else {
+            panic!("cuboids are instances");
+        };
+        let turned = Elem::Instance { blas: blas.clone(), transform: Box::new(Transform::rotate_y(45.0).then(&Transform::translate(vec3!(5.0, 0.0, 0.0)))) };
+        let raised = Elem::Instance { blas, transform: Box::new(Transform::translate(vec3!(0.0, 3.0, 0.0))) };
+
+        // Turned edge on, the cube's corner reaches root 2 back towards the ray, less its offset in z
+        let half_diagonal = 2f64.sqrt();
+        let dir = vec3!(1.0, 0.0, 0.0);
+        let mut r = Ray { origin: vec3!(0.0, 0.0, 0.3), dir, inv_dir: vec3!(1.0, f64::INFINITY, f64::INFINITY), time: 0.0, wavelength: None, differential: None };
+        let mut i = Intersection::new(0.001, f64::INFINITY);
+        turned.intersect(&mut r, &mut i, &mut Rng::with_seed(0));
+        let obj = i.obj.expect("expected a hit");
+        assert!(matches!(obj, Elem::Quad { .. }), "the face is recorded, not the instance");
+        assert!((i.max - (5.0 - half_diagonal + 0.3)).abs() < 1e-9);
+        r.move_along(i.max);
+        let sp = i.surface(obj, &r);
+        assert!((sp.normal - vec3!(-1.0, 0.0, 1.0).unit()).len() < 1e-9, "{:?}", sp.normal);
+        assert_near(nearest_hit(&raised, vec3!(0.0, 10.0, 0.0), vec3!(0.0, -1.0, 0.0)), 6.0);
+        assert!(nearest_hit(&raised, vec3!(-5.0, 0.0, 0.0), dir).is_none());
+
+        // Flat faces get padded bounds, so these only need to be close
+        let near = |a: Vec3, b: Vec3| (a - b).len() < 1e-3;
+        let bounds = turned.bounding_box(0.0, 1.0);
+        assert!(near(bounds.min, vec3!(5.0 - half_diagonal, -1.0, -half_diagonal)) && near(bounds.max, vec3!(5.0 + half_diagonal, 1.0, half_diagonal)), "{:?}", bounds);
+        let bounds = raised.bounding_box(0.0, 1.0);
+        assert!(near(bounds.min, vec3!(-1.0, 2.0, -1.0)) && near(bounds.max, vec3!(1.0, 4.0, 1.0)), "{:?}", bounds);
+    }
+
+    // How far rays through the medium along the x axis from x = -5 get before scattering, with
+    // infinity for those passing straight through
+    fn flights(medium: &Elem, seed: u64) -> Vec<f64> {
+        let mut rng = Rng::with_seed(seed);
+        let dir = vec3!(1.0, 0.0, 0.0);
+        (0..20_000).map(|_| {
+            let mut r = Ray { origin: vec3!(-5.0, 0.0, 0.0), dir, inv_dir: vec3!(1.0, f64::INFINITY, f64::INFINITY), time: 0.0, wavelength: None, differential: None };
+            let mut i = Intersection::new(0.001, f64::INFINITY);
+            medium.intersect(&mut r, &mut i, &mut rng);
+            i.obj.map_or(f64::INFINITY, |_| i.max)
+        }).collect()
+    }
+
+    #[test]
+    fn constant_medium_scatters_by_the_callers_rng() {
+        let sphere = Elem::Sphere { origin: vec3!(0.0, 0.0, 0.0), radius: 1.0, mat: Arc::new(Material::dielectric(1.5)) };
+        let mat = Arc::new(Material::Medium { albedo: Texture::constant(0.8), phase: PhaseFunction::Isotropic });
+        let fog = Elem::ConstantMedium { boundary: Box::new(sphere), density: 0.7, mat };
+
+        // The same seed gives the same flights, so renders repeat exactly
+        let seen = flights(&fog, 5);
+        assert_eq!(seen, flights(&fog, 5));
+        assert_ne!(seen, flights(&fog, 6));
+
+        // Across the sphere's diameter of 2, exp(-0.7 * 2) of the rays get through
+        let through = seen.iter().filter(|t| t.is_infinite()).count() as f64 / seen.len() as f64;
+        assert!((through - (-1.4f64).exp()).abs() < 0.01, "{} passed through", through);
+        assert!(seen.iter().all(|t| t.is_infinite() || (4.0..=6.0).contains(t)));
+    }
+
+    #[test]
+    fn heterogeneous_medium_tracks_by_the_callers_rng() {
+        // Half the majorant but in one corner, so half the tentative collisions are null
+        let mut data = vec![0.5; 18];
+        data[17] = 1.0;
+        let bounds = Aabb { min: vec3!(-1.0, -1.0, -1.0), max: vec3!(1.0, 1.0, 1.0) };
+        let grid = VoxelGrid::new([2, 3, 3], data, bounds).unwrap();
+        let sphere = Elem::Sphere { origin: vec3!(0.0, 0.0, 0.0), radius: 1.0, mat: Arc::new(Material::dielectric(1.5)) };
+        let mat = Arc::new(Material::Medium { albedo: Texture::constant(0.8), phase: PhaseFunction::Isotropic });
+        let cloud = Elem::HeterogeneousMedium { boundary: Box::new(sphere), density: Box::new(Density::Grid { grid, scale: 1.4 }), mat };
+
+        let seen = flights(&cloud, 5);
+        assert_eq!(seen, flights(&cloud, 5));
+        assert_ne!(seen, flights(&cloud, 6));
+
+        // The x axis runs through the centres of the middle row of voxels, at 0.7 throughout
+        let through = seen.iter().filter(|t| t.is_infinite()).count() as f64 / seen.len() as f64;
+        assert!((through - (-1.4f64).exp()).abs() < 0.01, "{} passed through", through);
+    }
+}
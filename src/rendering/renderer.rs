@@ -1,14 +1,19 @@
 use crate::scene::Scene;
 
 use super::Options;
-use super::materials::Material;
-use super::objects::{Intersection, Hittable};
-use super::math::{Ray, Vec3, vec3};
+use super::materials::{Dielectric, Material, Principled, Texture};
+use super::objects::{Intersection, Hittable, SurfacePoint};
+use super::math::{Frame, Ray, RayDifferential, Vec3, vec3};
+use super::spectrum::{RGB_WAVELENGTHS, Throughput, sample_wavelength, thin_film_reflectance, wavelength_rgb};
+use super::microfacet::{Ggx, fresnel_conductor, fresnel_dielectric, reflect, refract, schlick_weight};
 
 use fastrand::Rng;
 #[allow(unused_imports)]
 use rayon::prelude::*;
 
+// Paths still wandering inside a medium after this many scattering events are given up on
+const MAX_WALK_STEPS: u32 = 1024;
+
 #[allow(unused)]
 pub struct Renderer {
     scene: Scene,
@@ -74,6 +79,8 @@ impl Renderer {
         for _ in 0..self.options.pixel_samples {
             // Send a ray into the scene
             let mut ray = self.scene.cam.get_ray_to_pixel(x, y, &mut rng);
+            // Each of many samples only needs to cover its share of the pixel
+            ray.scale_differential((1.0 / (self.options.pixel_samples as f64).sqrt()).max(0.125));
             colour += self.calc_ray_colour(&mut ray, &mut rng);
         }
 
@@ -83,76 +90,697 @@ impl Renderer {
 
 
     fn calc_ray_colour(&self, ray: &mut Ray, rng: &mut Rng) -> Vec3 {
-        let mut colour = vec3!(1.0, 1.0, 1.0); 
-        let mut intersection = Intersection { min: 0.001, max: std::f64::INFINITY, obj: None };
+        let mut throughput = if self.options.spectral { Throughput::spectral(rng) } else { Throughput::Rgb(vec3!(1.0, 1.0, 1.0)) };
+        ray.wavelength = throughput.hero_wavelength();
+        let mut intersection = Intersection::new(0.001, f64::INFINITY);
+        // The dielectrics the ray is currently inside of, in the order they were entered. They're told
+        // apart by the address of their material, which the faces of a closed solid share
+        let mut media: Vec<&Material> = vec![];
         for _ in 0..self.options.ray_bounces {
             // Find the closest intersecting object
-            self.scene.bvh.intersect(ray, &mut intersection);
+            self.scene.bvh.intersect(ray, &mut intersection, rng);
+
+            // Random walk through the interior we're inside of until the path reaches its boundary.
+            // Steps of the walk don't use up bounces, dense media can take hundreds of them
+            let mut steps = 0;
+            while let (Some(medium), Some(_)) = (active(&media).and_then(|m| m.interior()), intersection.obj) {
+                let speed = ray.dir.len();
+                let (scatter, weight) = medium.sample_flight(intersection.max * speed, rng);
+                throughput.scale(&weight);
+                let Some(flight) = scatter else { break };
+
+                steps += 1;
+                if steps > MAX_WALK_STEPS {
+                    return vec3!(0.0, 0.0, 0.0);
+                }
+                ray.move_along(flight / speed);
+                ray.dir = medium.phase.sample(&ray.dir, rng);
+                ray.differential = None;
+                // Scattering points aren't on a surface, so search from right where they are or paths
+                // scattering close to the boundary would slip out past it, still inside the medium
+                intersection = Intersection::new(0.0, f64::INFINITY);
+                ray.inv_dir = vec3!(1.0 / ray.dir[0], 1.0 / ray.dir[1], 1.0 / ray.dir[2]);
+                self.scene.bvh.intersect(ray, &mut intersection, rng);
+            }
 
             // If we hit something, compute the next ray and it's colour
             if let Some(hit_obj) = intersection.obj {
                 // Move the ray to the intersection point and ready it for scattering
                 ray.move_along(intersection.max);
-                let normal = hit_obj.compute_normal(ray);
-                let (u, v) = hit_obj.compute_uv(&normal);
-
-                let (absorbed, attenuation) = match hit_obj.get_mat() {
-                    Material::Lambertian { albedo } => {
-                        ray.dir = normal + Vec3::random_unit_vec(rng);
-                        if Vec3::close_to_zero(ray.dir) {
-                            ray.dir = normal;
-                        }
-                        (false, albedo.colour(u, v, &ray.origin))
-                    },
-                    Material::Metal { albedo, fuzz } => {
-                        ray.dir = ray.dir.unit();
-                        ray.reflect(normal);
-                        ray.dir += Vec3::random_in_unit_sphere(rng) * *fuzz;
-                        (Vec3::dot(&ray.dir, &normal) < 0.0, albedo.colour(u, v, &ray.origin))
-                    },
-                    Material::Dielectric { mut ir } => {
-                        let albedo = vec3!(1.0, 1.0, 1.0);
-
-                        ray.dir = ray.dir.unit();
-                        let mut cos_thetai = Vec3::dot(&ray.dir, &normal).clamp(-1.0, 1.0);
-                        let mut n = normal;
-
-                        // Are we entering the medium
-                        if cos_thetai < 0.0 {
-                            cos_thetai = -cos_thetai;
-                            ir = 1.0 / ir;
-                        } else {
-                            n = -normal;
-                        }
-                        let sin_theta_sq = ir * ir * (1.0 - cos_thetai * cos_thetai);
-                        let cos_thetat = (1.0 - sin_theta_sq).sqrt();
-
-                        //Check for total internal reflection and viewing angles
-                        if sin_theta_sq > 1.0 || Ray::schlick(ir, cos_thetai) > fastrand::f64() {
-                            ray.reflect(n);
-                        } else {
-                            ray.refract(n, ir, cos_thetai, cos_thetat);
-                        }
-
-                        (false, albedo)
-                    }
-                };
+                let surface = intersection.surface(hit_obj, ray);
+                let mat = hit_obj.get_mat();
+                restart_differential(ray, &surface, mat);
+                let (absorbed, attenuation) = scatter(mat, ray, &surface, &mut media, rng);
             
                 if absorbed {
                     return vec3!(0.0, 0.0, 0.0);
                 }
 
-                colour = colour * attenuation; 
-                intersection.min = 0.001;
-                intersection.max = std::f64::INFINITY;
-                intersection.obj = None;
+                throughput.scale(&attenuation);
+                // Wavelength dependent surfaces send each wavelength their own way, so only the hero carries on
+                if mat.wavelength_dependent() {
+                    throughput.terminate_secondary();
+                }
+                intersection = Intersection::new(0.001, f64::INFINITY);
                 ray.inv_dir = vec3!(1.0 / ray.dir[0], 1.0 / ray.dir[1], 1.0 / ray.dir[2]);
             } else {
                 let t = 0.5 * (ray.dir[1] + 1.0);
-                return colour * (vec3!(1.0, 1.0, 1.0) * (1.0 - t) + self.scene.skybox_colour * t);
+                throughput.scale(&(vec3!(1.0, 1.0, 1.0) * (1.0 - t) + self.scene.skybox_colour * t));
+                return throughput.to_rgb();
             }
         }
         vec3!(0.0, 0.0, 0.0)
     }
 }
 
+// Scatters the ray off the material it hit, returning whether it was absorbed and its attenuation
+fn scatter<'a>(mat: &'a Material, ray: &mut Ray, sp: &SurfacePoint, media: &mut Vec<&'a Material>, rng: &mut Rng) -> (bool, Vec3) {
+    let normal = &sp.normal;
+    let (u, v) = sp.uv;
+    let at = ray.origin;
+    let colour = |t: &Texture| t.sample(u, v, &at, sp.footprint);
+    let value = |t: &Texture| colour(t)[0];
+    // Opaque materials scatter from the side the ray arrived on
+    let facing = if Vec3::dot(&ray.dir, &sp.geometric) > 0.0 { -normal } else { *normal };
+
+    match mat {
+        Material::Lambertian { albedo } => {
+            ray.dir = facing + Vec3::random_unit_vec(rng);
+            if Vec3::close_to_zero(ray.dir) {
+                ray.dir = facing;
+            }
+            (false, colour(albedo))
+        },
+        Material::OrenNayar { albedo, sigma } => {
+            let frame = Frame::new(&facing, &sp.dpdu);
+            let wo = frame.to_local(&-ray.dir.unit());
+            ray.dir = facing + Vec3::random_unit_vec(rng);
+            if Vec3::close_to_zero(ray.dir) {
+                ray.dir = facing;
+            }
+            let wi = frame.to_local(&ray.dir.unit());
+
+            // Cosine sampling cancels all but the albedo and the roughness terms
+            let sigma_sq = value(sigma).powi(2);
+            let a = 1.0 - sigma_sq / (2.0 * (sigma_sq + 0.33));
+            let b = 0.45 * sigma_sq / (sigma_sq + 0.09);
+
+            let sin_i = (1.0 - wi[2] * wi[2]).max(0.0).sqrt();
+            let sin_o = (1.0 - wo[2] * wo[2]).max(0.0).sqrt();
+            let mut rough = 0.0;
+            if sin_i > 1e-4 && sin_o > 1e-4 {
+                let cos_dphi = (wi[0] * wo[0] + wi[1] * wo[1]) / (sin_i * sin_o);
+                // Sine of the larger angle to the normal and tangent of the smaller
+                let (sin_alpha, tan_beta) = if wi[2] > wo[2] {
+                    (sin_o, sin_i / wi[2])
+                } else {
+                    (sin_i, sin_o / wo[2])
+                };
+                rough = cos_dphi.max(0.0) * sin_alpha * tan_beta;
+            }
+            (false, colour(albedo) * (a + b * rough))
+        },
+        Material::Metal { albedo, fuzz } => {
+            ray.dir = ray.dir.unit();
+            ray.reflect(facing, sp.normal_spread(ray, &facing));
+            ray.dir += Vec3::random_in_unit_sphere(rng) * *fuzz;
+            (Vec3::dot(&ray.dir, &facing) < 0.0, colour(albedo))
+        },
+        Material::Dielectric(d) => {
+            let (absorbed, _, weight) = cross_boundary(ray, sp, mat, d.roughness, media, rng);
+            (absorbed, weight)
+        },
+        Material::Medium { albedo, phase } => {
+            ray.dir = phase.sample(&ray.dir, rng);
+            (false, colour(albedo))
+        },
+        Material::Conductor { eta, k, roughness } => {
+            let frame = Frame::new(&facing, &sp.dpdu);
+            let wo = frame.to_local(&-ray.dir.unit());
+            let wm = roughness.sample_visible(&wo, rng.f64(), rng.f64());
+            reflect_microfacet(ray, sp, &frame, roughness, &wo, &wm, fresnel_conductor(Vec3::dot(&wo, &wm), eta, k))
+        },
+        Material::Principled(p) => scatter_principled(p, mat, ray, sp, media, rng),
+        Material::Coated { base, ir, roughness } => {
+            // Light leaving a transmissive base from inside doesn't meet the coating
+            if Vec3::dot(&ray.dir, &sp.geometric) > 0.0 && entered(base, media) {
+                return scatter(base, ray, sp, media, rng);
+            }
+
+            // Reflect off the coating with its Fresnel probability, otherwise carry on into the base
+            let ggx = Ggx::from_roughness(*roughness);
+            let frame = Frame::new(&facing, &sp.dpdu);
+            let wo = frame.to_local(&-ray.dir.unit());
+            let wm = ggx.sample_visible(&wo, rng.f64(), rng.f64());
+            if rng.f64() < fresnel_dielectric(Vec3::dot(&wo, &wm), 1.0 / ir) {
+                return reflect_microfacet(ray, sp, &frame, &ggx, &wo, &wm, vec3!(1.0, 1.0, 1.0));
+            }
+
+            // What the base sends back out loses the part the coating reflects back down
+            let (absorbed, attenuation) = scatter(base, ray, sp, media, rng);
+            let cos_out = Vec3::dot(&ray.dir.unit(), &facing);
+            if cos_out > 0.0 {
+                (absorbed, attenuation * (1.0 - fresnel_dielectric(cos_out, 1.0 / ir)))
+            } else {
+                (absorbed, attenuation)
+            }
+        },
+        Material::Bumped { base, map } => scatter(base, ray, &map.apply(sp, &ray.origin), media, rng),
+        Material::Mix { first, second, mask } => {
+            // A path inside one of the two leaves through it again, or it would never pop its medium
+            let use_second = match (entered(first, media), entered(second, media)) {
+                (true, _) => false,
+                (_, true) => true,
+                _ => rng.f64() < value(mask),
+            };
+            scatter(if use_second { second } else { first }, ray, sp, media, rng)
+        },
+    }
+}
+
+// Two media are the same only when they share one material, not when their materials are merely
+// equal. Solids made of several surfaces, like `Elem::cuboid`, share a material between them
+fn same_medium(a: &Material, b: &Material) -> bool {
+    std::ptr::eq(a, b)
+}
+
+// Whether the material, or one it's layered or mixed from, is a medium the ray is inside
+fn entered(mat: &Material, media: &[&Material]) -> bool {
+    match mat {
+        Material::Coated { base, .. } | Material::Bumped { base, .. } => entered(base, media),
+        Material::Mix { first, second, .. } => entered(first, media) || entered(second, media),
+        _ => media.iter().any(|m| same_medium(m, mat)),
+    }
+}
+
+// Of the overlapping media the ray is inside, the highest priority one fills the space,
+// with ties going to the most recently entered
+fn active<'a>(media: &[&'a Material]) -> Option<&'a Material> {
+    media.iter().max_by_key(|m| match m {
+        Material::Dielectric(d) => d.priority,
+        _ => 0,
+    }).copied()
+}
+
+// Outside of every dielectric the ray is travelling through air. Textured indices are read at
+// the boundary being crossed
+fn ior(medium: Option<&Material>, wavelength: Option<f64>, sp: &SurfacePoint, at: &Vec3) -> f64 {
+    match medium {
+        Some(Material::Dielectric(d)) => match (&d.dispersion, wavelength) {
+            (Some(dispersion), Some(l)) => dispersion.ior(l),
+            _ => d.ir,
+        },
+        Some(Material::Principled(p)) => p.ir.sample(sp.uv.0, sp.uv.1, at, sp.footprint)[0],
+        _ => 1.0,
+    }
+}
+
+// Only mirrors and clear glass keep the offset rays together, restarting them from the surface
+fn restart_differential(ray: &mut Ray, sp: &SurfacePoint, mat: &Material) {
+    ray.differential = match ray.differential {
+        Some(d) if mat.specular() => d.on_plane(&ray.origin, &sp.geometric)
+            .map(|(px, py)| RayDifferential { rx_origin: px, ry_origin: py, ..d }),
+        _ => None,
+    };
+}
+
+// Sends the ray off in dir, sampled from microfacets about n. Only surfaces smooth enough to count
+// as specular still carry offset rays, which go on as they would off the smooth surface, mirrored or
+// bent by the relative index ir, and turned with the sampled direction
+fn carry_differential(ray: &mut Ray, sp: &SurfacePoint, n: &Vec3, ir: f64, transmitted: bool, dir: Vec3) {
+    if ray.differential.is_some() {
+        ray.dir = ray.dir.unit();
+        let spread = sp.normal_spread(ray, n);
+        let cos_thetai = -Vec3::dot(&ray.dir, n);
+        let sin_theta_sq = ir * ir * (1.0 - cos_thetai * cos_thetai);
+        if !transmitted {
+            ray.reflect(*n, spread);
+        } else if sin_theta_sq < 1.0 {
+            ray.refract(*n, spread, ir, cos_thetai, (1.0 - sin_theta_sq).sqrt());
+        } else {
+            ray.differential = None;
+        }
+        let turn = dir.unit() - ray.dir.unit();
+        if let Some(d) = &mut ray.differential {
+            d.rx_dir = d.rx_dir.unit() + turn;
+            d.ry_dir = d.ry_dir.unit() + turn;
+        }
+    }
+    ray.dir = dir;
+}
+
+// Reflects off a visible microfacet, leaving the Fresnel and shadowing terms as the weight
+fn reflect_microfacet(ray: &mut Ray, sp: &SurfacePoint, frame: &Frame, ggx: &Ggx, wo: &Vec3, wm: &Vec3, fresnel: Vec3) -> (bool, Vec3) {
+    let wi = reflect(wo, wm);
+    carry_differential(ray, sp, &frame.normal, 1.0, false, frame.to_world(&wi));
+    (wi[2] <= 0.0, fresnel * (ggx.g2(wo, &wi) / ggx.g1(wo)))
+}
+
+// Reflects or refracts at the boundary of a transmissive material, entering or leaving it in the
+// medium stack. Returns whether the path was absorbed, whether it passed through and its weight
+fn cross_boundary<'a>(ray: &mut Ray, sp: &SurfacePoint, mat: &'a Material, roughness: f64, media: &mut Vec<&'a Material>, rng: &mut Rng) -> (bool, bool, Vec3) {
+    ray.dir = ray.dir.unit();
+
+    // Are we entering the medium, which the true surface decides even where the shading normal is bent
+    let entering = Vec3::dot(&ray.dir, &sp.geometric) < 0.0;
+    let n = if entering { sp.normal } else { -sp.normal };
+    let cos_thetai = (-Vec3::dot(&ray.dir, &n)).clamp(0.0, 1.0);
+
+    // The media either side of the boundary are whichever win out by priority
+    let mut beyond = media.clone();
+    if entering {
+        beyond.push(mat);
+    } else if let Some(pos) = beyond.iter().rposition(|m| same_medium(m, mat)) {
+        beyond.remove(pos);
+    }
+    let sides = [active(media), active(&beyond)];
+
+    // Dispersion splits white light, so from here on the path follows a single wavelength
+    let mut weight = vec3!(1.0, 1.0, 1.0);
+    if ray.wavelength.is_none() && sides.iter().any(|m| matches!(m, Some(Material::Dielectric(Dielectric { dispersion: Some(_), .. })))) {
+        let lambda = sample_wavelength(rng);
+        ray.wavelength = Some(lambda);
+        weight = wavelength_rgb(lambda);
+    }
+    let wavelength = ray.wavelength;
+    let (n1, n2) = (ior(sides[0], wavelength, sp, &ray.origin), ior(sides[1], wavelength, sp, &ray.origin));
+    let ir = n1 / n2;
+
+    // A film shows wherever its boundary isn't hidden, even between matching indices like a bubble's
+    let film = match mat {
+        Material::Dielectric(Dielectric { film: Some(film), .. }) if sides.iter().any(|m| m.is_some_and(|m| same_medium(m, mat))) => Some(*film),
+        _ => None,
+    };
+    let reflectance = |cos_i: f64| match film {
+        Some(f) => match wavelength {
+            Some(l) => grey(thin_film_reflectance(cos_i, n1, f.ir, n2, f.thickness, l)),
+            None => {
+                let [r, g, b] = RGB_WAVELENGTHS.map(|l| thin_film_reflectance(cos_i, n1, f.ir, n2, f.thickness, l));
+                vec3!(r, g, b)
+            },
+        },
+        None if roughness > 0.0 => grey(fresnel_dielectric(cos_i, ir)),
+        None => grey(Ray::schlick(ir, cos_i)),
+    };
+
+    // Boundaries hidden inside a higher priority medium are passed straight through
+    let mut transmitted = true;
+    let mut absorbed = false;
+    if (n1 != n2 || film.is_some()) && roughness > 0.0 {
+        // Reflect or refract through a visible microfacet, chosen by its Fresnel term
+        let ggx = Ggx::from_roughness(roughness);
+        let frame = Frame::new(&n, &sp.dpdu);
+        let wo = frame.to_local(&-ray.dir);
+        let wm = ggx.sample_visible(&wo, rng.f64(), rng.f64());
+
+        let refracted = refract(&wo, &wm, ir).filter(|_| !pick_reflection(reflectance(Vec3::dot(&wo, &wm)), &mut weight, rng));
+        transmitted = refracted.is_some();
+        let wi = match refracted {
+            Some(wt) => {
+                absorbed = wt[2] >= 0.0;
+                wt
+            },
+            None => {
+                let wr = reflect(&wo, &wm);
+                absorbed = wr[2] <= 0.0;
+                wr
+            },
+        };
+        carry_differential(ray, sp, &n, ir, transmitted, frame.to_world(&wi));
+        weight *= ggx.g2(&wo, &wi) / ggx.g1(&wo);
+    } else if n1 != n2 || film.is_some() {
+        let sin_theta_sq = ir * ir * (1.0 - cos_thetai * cos_thetai);
+        let cos_thetat = (1.0 - sin_theta_sq).sqrt();
+
+        //Check for total internal reflection and viewing angles
+        if sin_theta_sq > 1.0 || pick_reflection(reflectance(cos_thetai), &mut weight, rng) {
+            ray.reflect(n, sp.normal_spread(ray, &n));
+            transmitted = false;
+        } else {
+            ray.refract(n, sp.normal_spread(ray, &n), ir, cos_thetai, cos_thetat);
+        }
+    }
+    if transmitted {
+        *media = beyond;
+    }
+
+    (absorbed, transmitted, weight)
+}
+
+// Reflects with the mean of a per channel reflectance, reweighting so each channel gets its own share
+fn pick_reflection(reflectance: Vec3, weight: &mut Vec3, rng: &mut Rng) -> bool {
+    let p = (reflectance[0] + reflectance[1] + reflectance[2]) / 3.0;
+    if rng.f64() < p {
+        *weight = *weight * reflectance / p;
+        true
+    } else {
+        *weight = *weight * (vec3!(1.0, 1.0, 1.0) - reflectance) / (1.0 - p);
+        false
+    }
+}
+
+fn grey(v: f64) -> Vec3 {
+    vec3!(v, v, v)
+}
+
+// Scatters off the principled material by picking one of its layers from the top down, each with
+// the probability that light reflects from it rather than passing on to the layers beneath
+fn scatter_principled<'a>(p: &Principled, mat: &'a Material, ray: &mut Ray, sp: &SurfacePoint, media: &mut Vec<&'a Material>, rng: &mut Rng) -> (bool, Vec3) {
+    let (u, v) = sp.uv;
+    let at = ray.origin;
+    let colour = |t: &Texture| t.sample(u, v, &at, sp.footprint);
+    let value = |t: &Texture| colour(t)[0];
+    let white = vec3!(1.0, 1.0, 1.0);
+    let base = colour(&p.base_colour);
+    let roughness = value(&p.roughness);
+    // Tint by the root of the base colour at each crossing, so passing in and out tints by it once
+    let tint = vec3!(base[0].sqrt(), base[1].sqrt(), base[2].sqrt());
+
+    // Leaving a transmissive interior only crosses the boundary, the coatings are on the outside
+    let backside = Vec3::dot(&ray.dir, &sp.geometric) > 0.0;
+    if backside && media.iter().any(|m| same_medium(m, mat)) {
+        let (absorbed, transmitted, weight) = cross_boundary(ray, sp, mat, roughness, media, rng);
+        return (absorbed, if transmitted { tint * weight } else { weight });
+    }
+
+    let facing = if backside { -sp.normal } else { sp.normal };
+    let frame = Frame::new(&facing, &sp.dpdu);
+    let wo = frame.to_local(&-ray.dir.unit());
+
+    // A clear lacquer on top, with the reflectance of a 1.5 index coating
+    let clearcoat = value(&p.clearcoat);
+    if clearcoat > 0.0 {
+        let ggx = Ggx::from_roughness(value(&p.clearcoat_roughness));
+        let wm = ggx.sample_visible(&wo, rng.f64(), rng.f64());
+        if rng.f64() < clearcoat * fresnel_dielectric(Vec3::dot(&wo, &wm), 1.0 / 1.5) {
+            return reflect_microfacet(ray, sp, &frame, &ggx, &wo, &wm, white);
+        }
+    }
+
+    // Metals reflect everything, coloured by the base at normal incidence
+    let ggx = Ggx::from_roughness(roughness);
+    if rng.f64() < value(&p.metallic) {
+        let wm = ggx.sample_visible(&wo, rng.f64(), rng.f64());
+        let fresnel = base + (white - base) * schlick_weight(Vec3::dot(&wo, &wm));
+        return reflect_microfacet(ray, sp, &frame, &ggx, &wo, &wm, fresnel);
+    }
+
+    if rng.f64() < value(&p.transmission) {
+        let (absorbed, transmitted, weight) = cross_boundary(ray, sp, mat, roughness, media, rng);
+        return (absorbed, if transmitted { tint * weight } else { weight });
+    }
+
+    // Otherwise a dielectric specular over a diffuse base
+    let wm = ggx.sample_visible(&wo, rng.f64(), rng.f64());
+    let f0 = 0.08 * value(&p.specular);
+    if rng.f64() < f0 + (1.0 - f0) * schlick_weight(Vec3::dot(&wo, &wm)) {
+        return reflect_microfacet(ray, sp, &frame, &ggx, &wo, &wm, white);
+    }
+
+    ray.dir = facing + Vec3::random_unit_vec(rng);
+    if Vec3::close_to_zero(ray.dir) {
+        ray.dir = facing;
+    }
+    // Sheen brightens the diffuse towards grazing angles like the fibres of cloth
+    let wi = frame.to_local(&ray.dir.unit());
+    let half = (wi + wo).unit();
+    let sheen = colour(&p.sheen) * (std::f64::consts::PI * schlick_weight(Vec3::dot(&wi, &half)));
+    (false, base + sheen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::bvh::{BvhTree, LinearBvh};
+    use crate::camera::{Camera, CamSettings};
+    use crate::materials::{Dispersion, ThinFilm};
+    use crate::math::Transform;
+    use crate::media::{HomogeneousMedium, PhaseFunction};
+    use crate::objects::Elem;
+
+    fn ray(origin: Vec3, dir: Vec3) -> Ray {
+        Ray { origin, dir, inv_dir: vec3!(1.0 / dir[0], 1.0 / dir[1], 1.0 / dir[2]), time: 0.0, wavelength: None, differential: None }
+    }
+
+    // A horizontal boundary through the origin facing up
+    fn flat_surface() -> SurfacePoint {
+        let up = vec3!(0.0, 1.0, 0.0);
+        SurfacePoint { normal: up, geometric: up, dpdu: vec3!(1.0, 0.0, 0.0), dpdv: vec3!(0.0, 0.0, 1.0), dndu: vec3!(0.0, 0.0, 0.0), dndv: vec3!(0.0, 0.0, 0.0), uv: (0.0, 0.0), footprint: 0.0 }
+    }
+
+    // Scatters the ray around the object until it escapes, returning the media it's still inside of
+    // and how many times it hit the object, or None if it was absorbed
+    fn trace<'a>(obj: &'a Elem, ray: &mut Ray, rng: &mut Rng) -> Option<(Vec<&'a Material>, u32)> {
+        let mut media = vec![];
+        let mut hits = 0;
+        while hits < 1000 {
+            let mut intersection = Intersection::new(0.001, f64::INFINITY);
+            obj.intersect(ray, &mut intersection, rng);
+            let Some(hit) = intersection.obj else { break };
+            hits += 1;
+            ray.move_along(intersection.max);
+            let surface = intersection.surface(hit, ray);
+            if scatter(hit.get_mat(), ray, &surface, &mut media, rng).0 {
+                return None;
+            }
+            ray.inv_dir = vec3!(1.0 / ray.dir[0], 1.0 / ray.dir[1], 1.0 / ray.dir[2]);
+        }
+        Some((media, hits))
+    }
+
+    #[test]
+    fn leaving_a_solid_pops_its_medium() {
+        let oil = ThinFilm { ir: 1.45, thickness: 500.0 };
+        let materials = [
+            Material::dielectric(1.5),
+            Material::Dielectric(Dielectric { roughness: 0.2, ..Dielectric::new(1.31) }),
+            Material::Dielectric(Dielectric { film: Some(oil), ..Dielectric::new(1.33) }),
+            Material::Dielectric(Dielectric { dispersion: Some(Dispersion::SF11), ..Dielectric::new(1.78) }),
+        ];
+        let mut rng = Rng::with_seed(7);
+        for mat in materials {
+            let cuboid = Elem::cuboid(vec3!(-1.0, -1.0, -1.0), vec3!(1.0, 1.0, 1.0), mat.clone());
+            let solids = [
+                Elem::Sphere { origin: vec3!(0.0, 0.0, 0.0), radius: 1.0, mat: Arc::new(mat) },
+                Elem::Transformed { obj: Box::new(cuboid), transform: Box::new(Transform::rotate_z(45.0).then(&Transform::rotate_y(30.0))) },
+            ];
+            for solid in &solids {
+                let mut escaped = 0;
+                for _ in 0..100 {
+                    let mut r = ray(vec3!(-3.0, 0.5, 0.2), vec3!(1.0, -0.3, 0.1));
+                    if let Some((media, _)) = trace(solid, &mut r, &mut rng) {
+                        assert!(media.is_empty(), "{:?} left its medium on the stack", solid);
+                        escaped += 1;
+                    }
+                }
+                assert!(escaped > 50);
+            }
+        }
+    }
+
+    #[test]
+    fn absorbing_interior_follows_beer_lambert() {
+        // With an index of one the slab's faces neither reflect nor bend, leaving only absorption
+        let absorption = vec3!(0.2, 0.5, 1.0);
+        let interior = HomogeneousMedium { absorption, scattering: vec3!(0.0, 0.0, 0.0), phase: PhaseFunction::Isotropic };
+        let slab = Elem::cuboid(vec3!(-0.75, -5.0, -5.0), vec3!(0.75, 5.0, 5.0), Material::Dielectric(Dielectric { interior: Some(interior), ..Dielectric::new(1.0) }));
+        let cs = CamSettings { view_width: 1, view_height: 1, vfov: 90.0, focus_dist: 1.0, aperture: 0.0 };
+        let scene = Scene {
+            cam: Camera::new(vec3!(-3.0, 0.0, 0.0), vec3!(0.0, 0.0, 0.0), cs, 0.0, 0.0),
+            bvh: LinearBvh::new(BvhTree::new(vec![slab], 0.0, 0.0)),
+            skybox_colour: vec3!(1.0, 1.0, 1.0),
+        };
+        let renderer = Renderer::new(1, 1, Options { pixel_samples: 1, ray_bounces: 8, spectral: false }, scene);
+
+        // Straight across, then at an angle where the path inside is longer by one over the cosine
+        let mut rng = Rng::with_seed(1);
+        for (dir, dist) in [(vec3!(1.0, 0.0, 0.0), 1.5), (vec3!(2.0, 1.0, 0.0), 1.5 * 5.0f64.sqrt() / 2.0)] {
+            let colour = renderer.calc_ray_colour(&mut ray(vec3!(-3.0, 0.0, 0.0), dir), &mut rng);
+            for c in 0..3 {
+                let expected = (-absorption[c] * dist).exp();
+                assert!((colour[c] - expected).abs() < 1e-9, "channel {} transmits {} rather than {}", c, colour[c], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn prioritised_cuboid_refracts_on_the_way_out() {
+        let ice = Material::Dielectric(Dielectric { priority: 4, ..Dielectric::new(1.31) });
+        let slab = Elem::cuboid(vec3!(-5.0, -0.5, -5.0), vec3!(5.0, 0.5, 5.0), ice);
+        let dir = vec3!(0.4, -1.0, 0.2).unit();
+        let mut rng = Rng::with_seed(3);
+        let mut through = 0;
+        for _ in 0..100 {
+            let mut r = ray(vec3!(0.0, 2.0, 0.0), dir);
+            let (media, hits) = trace(&slab, &mut r, &mut rng).unwrap();
+            assert!(media.is_empty());
+            // Straight through both faces, the parallel faces bend the ray back to where it started
+            if hits == 2 && r.dir[1] < 0.0 {
+                through += 1;
+                assert!((r.dir.unit() - dir).len() < 1e-9);
+            }
+        }
+        assert!(through > 50);
+    }
+
+    #[test]
+    fn leaving_a_rough_cuboid_pops_its_medium() {
+        let frosted = Material::Dielectric(Dielectric { roughness: 0.2, ..Dielectric::new(1.31) });
+        let cuboid = Elem::cuboid(vec3!(-1.0, -1.0, -1.0), vec3!(1.0, 1.0, 1.0), frosted);
+        let mut rng = Rng::with_seed(11);
+        let mut escaped = 0;
+        for _ in 0..200 {
+            let mut r = ray(vec3!(-3.0, 0.5, 0.2), vec3!(1.0, -0.3, 0.1));
+            if let Some((media, _)) = trace(&cuboid, &mut r, &mut rng) {
+                assert!(media.is_empty());
+                escaped += 1;
+            }
+        }
+        assert!(escaped > 100);
+    }
+
+    #[test]
+    fn leaving_filmed_and_dispersive_cuboids_pops_their_medium() {
+        let oil = ThinFilm { ir: 1.45, thickness: 500.0 };
+        let filmed = Material::Dielectric(Dielectric { film: Some(oil), ..Dielectric::new(1.33) });
+        let flint = Material::Dielectric(Dielectric { dispersion: Some(Dispersion::SF11), ..Dielectric::new(1.78) });
+        let solids = [
+            Elem::cuboid(vec3!(-1.0, -1.0, -1.0), vec3!(1.0, 1.0, 1.0), filmed),
+            Elem::Transformed {
+                obj: Box::new(Elem::cuboid(vec3!(-1.0, -1.0, -1.0), vec3!(1.0, 1.0, 1.0), flint)),
+                transform: Box::new(Transform::rotate_z(45.0).then(&Transform::rotate_y(30.0))),
+            },
+        ];
+        let mut rng = Rng::with_seed(5);
+        for solid in &solids {
+            for _ in 0..100 {
+                let mut r = ray(vec3!(-3.0, 0.5, 0.2), vec3!(1.0, -0.3, 0.1));
+                assert!(trace(solid, &mut r, &mut rng).unwrap().0.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_splits_energy_by_fresnel() {
+        let mut rng = Rng::with_seed(2);
+        let n = 20000;
+        // Reflected and transmitted energy of rays meeting a horizontal boundary at an angle from
+        // the outside or the inside of the material
+        let mut split = |glass: &Material, roughness: f64, degrees: f64, inside: bool| {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            let (mut reflected, mut transmitted) = (0.0, 0.0);
+            for _ in 0..n {
+                let mut media = if inside { vec![glass] } else { vec![] };
+                let mut r = ray(vec3!(0.0, 0.0, 0.0), vec3!(sin, if inside { cos } else { -cos }, 0.0));
+                match cross_boundary(&mut r, &flat_surface(), glass, roughness, &mut media, &mut rng) {
+                    (true, ..) => (),
+                    (false, true, w) => transmitted += w[0],
+                    (false, false, w) => reflected += w[0],
+                }
+            }
+            (reflected / n as f64, transmitted / n as f64)
+        };
+
+        // Barely rough glass splits light as the smooth Fresnel equations do, losing none of it
+        let glass = Material::Dielectric(Dielectric { roughness: 0.01, ..Dielectric::new(1.5) });
+        for (degrees, inside) in [(0.0, false), (75.0, false), (0.0, true), (45.0, true)] {
+            let (r, t) = split(&glass, 0.01, degrees, inside);
+            let fresnel = fresnel_dielectric(degrees.to_radians().cos(), if inside { 1.5 } else { 1.0 / 1.5 });
+            assert!((r - fresnel).abs() < 0.01, "reflected {} rather than {} at {} degrees", r, fresnel, degrees);
+            assert!((r + t - 1.0).abs() < 1e-6);
+        }
+
+        // Rougher glass only loses the little energy that single scattering microfacets can't return
+        for roughness in [0.3, 0.6] {
+            let glass = Material::Dielectric(Dielectric { roughness, ..Dielectric::new(1.5) });
+            for degrees in [0.0, 45.0, 75.0] {
+                let (r, t) = split(&glass, roughness, degrees, false);
+                assert!(r + t <= 1.0 + 1e-6 && r + t > 0.85, "roughness {} at {} degrees keeps {}", roughness, degrees, r + t);
+            }
+        }
+    }
+
+    #[test]
+    fn leaving_a_mixed_solid_pops_its_medium() {
+        // Paths that enter through one child and are picked to leave through the other must still
+        // leave the medium of the one they entered
+        let glass = Material::dielectric(1.5);
+        let frosted = Material::Dielectric(Dielectric { roughness: 0.3, ..Dielectric::new(1.5) });
+        let mixes = [
+            Material::Mix { first: Box::new(glass.clone()), second: Box::new(frosted), mask: Texture::constant(0.5) },
+            Material::Mix { first: Box::new(glass), second: Box::new(Material::Lambertian { albedo: Texture::constant(0.8) }), mask: Texture::constant(0.5) },
+        ];
+        let mut rng = Rng::with_seed(13);
+        for mix in mixes {
+            let sphere = Elem::Sphere { origin: vec3!(0.0, 0.0, 0.0), radius: 1.0, mat: Arc::new(mix) };
+            for _ in 0..200 {
+                let mut r = ray(vec3!(-3.0, 0.5, 0.2), vec3!(1.0, -0.3, 0.1));
+                if let Some((media, _)) = trace(&sphere, &mut r, &mut rng) {
+                    assert!(media.is_empty());
+                }
+            }
+        }
+    }
+
+    // Follows the ray through specular bounces off the objects until it reaches the last of them,
+    // a screen, returning where it lands and the footprint there. None if it took a different path
+    // from the one expected, given as the number of bounces before the screen
+    fn land(objs: &[Elem], mut r: Ray, bounces: usize, rng: &mut Rng) -> Option<((f64, f64), f64)> {
+        let mut media = vec![];
+        for bounce in 0..=bounces {
+            let mut intersection = Intersection::new(0.001, f64::INFINITY);
+            for obj in objs {
+                obj.intersect(&mut r, &mut intersection, rng);
+            }
+            let hit = intersection.obj?;
+            let on_screen = std::ptr::eq(hit, objs.last().unwrap());
+            if on_screen != (bounce == bounces) {
+                return None;
+            }
+            r.move_along(intersection.max);
+            let surface = intersection.surface(hit, &r);
+            if on_screen {
+                return Some((surface.uv, surface.footprint));
+            }
+            restart_differential(&mut r, &surface, hit.get_mat());
+            scatter(hit.get_mat(), &mut r, &surface, &mut media, rng);
+            r.inv_dir = vec3!(1.0 / r.dir[0], 1.0 / r.dir[1], 1.0 / r.dir[2]);
+        }
+        None
+    }
+
+    #[test]
+    fn curved_surfaces_spread_differentials_like_neighbouring_rays() {
+        let mirror = Arc::new(Material::Metal { albedo: Texture::constant(0.9), fuzz: 0.0 });
+        let glass = Arc::new(Material::dielectric(1.5));
+        let sphere = |mat: &Arc<Material>| Elem::Sphere { origin: vec3!(0.0, 0.0, 0.0), radius: 1.0, mat: mat.clone() };
+        // Screens well away from the sphere, so the curvature magnifies the footprint
+        let screen = |z: f64| Elem::Quad { corner: vec3!(-50.0, -50.0, z), u: vec3!(100.0, 0.0, 0.0), v: vec3!(0.0, 100.0, 0.0), mat: mirror.clone() };
+        let squashed = Elem::Transformed { obj: Box::new(sphere(&mirror)), transform: Box::new(Transform::scale(vec3!(1.5, 0.7, 1.0))) };
+        // Mirrors bounce once back towards the camera's side, glass bends twice on the way through
+        let scenes = [
+            (vec![sphere(&mirror), screen(8.0)], 1),
+            (vec![squashed, screen(8.0)], 1),
+            (vec![Elem::Torus { origin: vec3!(0.0, 0.0, 0.0), major: 1.5, minor: 0.5, mat: mirror.clone() }, screen(8.0)], 1),
+            (vec![sphere(&glass), screen(-10.0)], 2),
+        ];
+
+        let mut rng = Rng::with_seed(3);
+        let (origin, dir) = (vec3!(0.0, 0.0, 5.0), vec3!(0.06, 0.05, -1.0));
+        let (dx, dy) = (vec3!(1e-4, 0.0, 0.0), vec3!(0.0, 1e-4, 0.0));
+        for (objs, bounces) in &scenes {
+            // Glass reflects some of the time, so retry until each ray goes the expected way
+            let mut follow = |r: Ray| (0..100).find_map(|_| land(objs, Ray { ..r }, *bounces, &mut rng)).unwrap();
+            let mut main = ray(origin, dir);
+            main.differential = Some(RayDifferential { rx_origin: origin, rx_dir: dir + dx, ry_origin: origin, ry_dir: dir + dy });
+            let (uv, footprint) = follow(main);
+            let (uv_x, _) = follow(ray(origin, dir + dx));
+            let (uv_y, _) = follow(ray(origin, dir + dy));
+            let reach = |(u, v): (f64, f64)| ((u - uv.0).powi(2) + (v - uv.1).powi(2)).sqrt();
+            let expected = reach(uv_x).max(reach(uv_y));
+            assert!((footprint / expected - 1.0).abs() < 0.02, "footprint {} against {} from neighbouring rays", footprint, expected);
+        }
+    }
+}
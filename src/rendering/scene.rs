@@ -1,10 +1,13 @@
+use std::sync::Arc;
+
 use crate::{
+    animation::Animation,
     camera::Camera,
     materials::Material,
-    math::{Vec3, vec3}, bvh::{BvhTree, LinearBvh},
+    math::{AnimatedTransform, Keyframe, Quat, Transform, Vec3, vec3}, bvh::{BvhTree, LinearBvh},
 };
 
-use super::{objects::Elem, camera::CamSettings, materials::{Texture, PerlinNoise}};
+use super::{objects::Elem, camera::{CamKeyframe, CamSettings}, materials::{ConductorPreset, Dielectric, Dispersion, Principled, SurfaceMap, Texture, ThinFilm, PerlinNoise}, image_texture::{ColourSpace, Filter, ImageTexture, TextureCache, UvTransform, Wrap}, aabb::Aabb, media::{Density, HomogeneousMedium, PhaseFunction, VoxelGrid}};
 
 pub struct Scene {
     pub cam: Camera,
@@ -22,7 +25,7 @@ pub fn simple_scene(width: u32, height: u32) -> Scene {
     objs.push(Elem::Sphere { 
         origin: vec3!(0.0, -100.5, -1.0), 
         radius: 100.0,
-        mat: ground_material,
+        mat: Arc::new(ground_material),
     });
     let material2 = Material::Lambertian {
         albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) },
@@ -30,7 +33,7 @@ pub fn simple_scene(width: u32, height: u32) -> Scene {
     objs.push(Elem::Sphere {
         origin: vec3!(0.0, 0.0, -1.0),
         radius: 0.5,
-        mat: material2,
+        mat: Arc::new(material2),
     });
 
     let from = vec3!(0.0, 0.0, 0.0);
@@ -57,32 +60,33 @@ pub fn three_spheres_scene(width: u32, height: u32) -> Scene {
     let mut objs: Vec<Elem> = vec![];
     let ground_mat = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.8, 0.8, 0.0) } };
     let mat1 = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.1, 0.2, 0.5) } };
-    let mat2 = Material::Dielectric { ir: 1.5 };
+    let mat2 = Material::dielectric(1.5);
     let mat3 = Material::Metal { albedo: Texture::Solid { colour: vec3!(0.8, 0.6, 0.2) }, fuzz: 0.0 };
     objs.push(Elem::Sphere { 
         origin: vec3!(0.0, -100.5, -1.0), 
         radius: 100.0,
-        mat: ground_mat,
+        mat: Arc::new(ground_mat),
     });
     objs.push(Elem::Sphere {
         origin: vec3!(0.0, 0.0, -1.0),
         radius: 0.5,
-        mat: mat1,
+        mat: Arc::new(mat1),
     });
     objs.push(Elem::Sphere {
         origin: vec3!(-1.0, 0.0, -1.0),
         radius: 0.5,
-        mat: mat2.clone(),
+        mat: Arc::new(mat2),
     });
+    // A bubble of air inside the glass, carved out by its higher priority
     objs.push(Elem::Sphere {
         origin: vec3!(-1.0, 0.0, -1.0),
-        radius: -0.45,
-        mat: mat2,
+        radius: 0.45,
+        mat: Arc::new(Material::Dielectric(Dielectric { priority: 1, ..Dielectric::new(1.0) })),
     });
     objs.push(Elem::Sphere {
         origin: vec3!(1.0, 0.0, -1.0),
         radius: 0.5,
-        mat: mat3,
+        mat: Arc::new(mat3),
     });
 
     let from = vec3!(-2.0, 2.0, 1.0);
@@ -113,7 +117,7 @@ pub fn weekend_scene(width: u32, height: u32) -> Scene {
     objs.push(Elem::Sphere { 
         origin: vec3!(0.0, -1000.0, 0.0), 
         radius: 1000.0,
-        mat: ground_material,
+        mat: Arc::new(ground_material),
     });
 
     for a in -11..11 {
@@ -135,36 +139,36 @@ pub fn weekend_scene(width: u32, height: u32) -> Scene {
                     sphere_material = Material::Metal { albedo, fuzz };
                 } else {
                     // glass
-                    sphere_material = Material::Dielectric { ir: 1.5 };
+                    sphere_material = Material::dielectric(1.5);
                 }
                 objs.push(Elem::Sphere { 
                     origin: center, 
                     radius: 0.2, 
-                    mat: sphere_material,
+                    mat: Arc::new(sphere_material),
                 });
             }
         }
     }
 
-    let material1 = Material::Dielectric { ir: 1.5 };
+    let material1 = Material::dielectric(1.5);
     objs.push(Elem::Sphere {
         origin: vec3!(0.0, 1.0, 0.0),
         radius: 1.0,
-        mat: material1,
+        mat: Arc::new(material1),
     });
 
     let material2 = Material::Lambertian{ albedo: Texture::Solid { colour: vec3!(0.4, 0.2, 0.1) } };
     objs.push(Elem::Sphere {
         origin: vec3!(-4.0, 1.0, 0.0),
         radius: 1.0,
-        mat: material2,
+        mat: Arc::new(material2),
     });
 
     let material3 = Material::Metal{ albedo: Texture::Solid { colour: vec3!(0.7, 0.6, 0.5) }, fuzz: 0.0 };
     objs.push(Elem::Sphere {
         origin: vec3!(4.0, 1.0, 0.0),
         radius: 1.0,
-        mat: material3,
+        mat: Arc::new(material3),
     });
 
     let from = vec3!(13.0, 2.0, 3.0);
@@ -200,7 +204,7 @@ pub fn weekend_scene_bouncing(width: u32, height: u32) -> Scene {
     objs.push(Elem::Sphere { 
         origin: vec3!(0.0, -1000.0, 0.0), 
         radius: 1000.0,
-        mat: ground_material,
+        mat: Arc::new(ground_material),
     });
 
     for a in -11..11 {
@@ -222,7 +226,7 @@ pub fn weekend_scene_bouncing(width: u32, height: u32) -> Scene {
                         time0: 0.0,
                         time1: 1.0,
                         radius: 0.2, 
-                        mat: sphere_material,
+                        mat: Arc::new(sphere_material),
                     });
                 } else {
                     if choose_mat < 0.95 {
@@ -232,37 +236,37 @@ pub fn weekend_scene_bouncing(width: u32, height: u32) -> Scene {
                         sphere_material = Material::Metal { albedo, fuzz };
                     } else {
                         // glass
-                        sphere_material = Material::Dielectric { ir: 1.5 };
+                        sphere_material = Material::dielectric(1.5);
                     }
                     objs.push(Elem::Sphere { 
                         origin: center, 
                         radius: 0.2, 
-                        mat: sphere_material,
+                        mat: Arc::new(sphere_material),
                     });
                 }
             }
         }
     }
 
-    let material1 = Material::Dielectric { ir: 1.5 };
+    let material1 = Material::dielectric(1.5);
     objs.push(Elem::Sphere {
         origin: vec3!(0.0, 1.0, 0.0),
         radius: 1.0,
-        mat: material1,
+        mat: Arc::new(material1),
     });
 
     let material2 = Material::Lambertian{ albedo: Texture::Solid { colour: vec3!(0.4, 0.2, 0.1) } };
     objs.push(Elem::Sphere {
         origin: vec3!(-4.0, 1.0, 0.0),
         radius: 1.0,
-        mat: material2,
+        mat: Arc::new(material2),
     });
 
     let material3 = Material::Metal{ albedo: Texture::Solid { colour: vec3!(0.7, 0.6, 0.5) }, fuzz: 0.0 };
     objs.push(Elem::Sphere {
         origin: vec3!(4.0, 1.0, 0.0),
         radius: 1.0,
-        mat: material3,
+        mat: Arc::new(material3),
     });
 
     let from = vec3!(13.0, 2.0, 3.0);
@@ -293,12 +297,12 @@ pub fn perlin_scene(width: u32, height: u32) -> Scene {
     objs.push(Elem::Sphere { 
         origin: vec3!(0.0, -1000.0, 0.0), 
         radius: 1000.0,
-        mat: perlin_mat.clone(),
+        mat: Arc::new(perlin_mat.clone()),
     });
     objs.push(Elem::Sphere { 
         origin: vec3!(0.0, 2.0, 0.0), 
         radius: 2.0,
-        mat: perlin_mat,
+        mat: Arc::new(perlin_mat),
     });
     let from = vec3!(13.0, 2.0, 3.0);
     let at = vec3!(0.0, 0.0, 0.0);
@@ -320,12 +324,16 @@ pub fn perlin_scene(width: u32, height: u32) -> Scene {
 }
 
 #[allow(unused)]
-pub fn earth_scene(width: u32, height: u32) -> Scene {
-    let mut objs: Vec<Elem> = vec![Elem::Sphere { 
+pub fn earth_scene(width: u32, height: u32) -> Result<Scene, image::ImageError> {
+    let mut textures = TextureCache::default();
+    let earth = Elem::Sphere { 
         origin: vec3!(0.0, 0.0, 0.0), 
         radius: 2.0,
-        mat: Material::Lambertian { albedo: Texture::Image { img: image::open("earthmap.jpg").unwrap().to_rgb8() } },
-    }];
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Image { img: textures.load("earthmap.jpg", ColourSpace::Srgb)? } }),
+    };
+    // Spin the earth to face the camera and tilt it on its axis
+    let tilt = Transform::rotate_y(-60.0).then(&Transform::rotate_z(23.4));
+    let mut objs: Vec<Elem> = vec![Elem::Transformed { obj: Box::new(earth), transform: Box::new(tilt) }];
     let from = vec3!(13.0, 2.0, 3.0);
     let at = vec3!(0.0, 0.0, 0.0);
 
@@ -337,10 +345,1063 @@ pub fn earth_scene(width: u32, height: u32) -> Scene {
         aperture: 0.0,
     };
     let cam = Camera::new(from, at, cs, 0.0, 0.0);
-    Scene {
+    Ok(Scene {
         cam, 
         skybox_colour: vec3!(0.5, 0.7, 1.0),
         bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
         //bvh: BvhTree::new(objs, 0.0, 0.0),
+    })
+}
+
+#[allow(unused)]
+pub fn forest_scene(width: u32, height: u32) -> Scene {
+    let mut rng = fastrand::Rng::new();
+    rng.seed(10);
+
+    // A single tree built once and shared by every instance
+    let bark = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.4, 0.25, 0.1) } };
+    let leaves = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.1, 0.5, 0.1) } };
+    let mut tree: Vec<Elem> = vec![];
+    for i in 0..6 {
+        tree.push(Elem::Sphere {
+            origin: vec3!(0.0, 0.1 + i as f64 * 0.15, 0.0),
+            radius: 0.1,
+            mat: Arc::new(bark.clone()),
+        });
+    }
+    for _ in 0..12 {
+        let offset = Vec3::random_in_unit_sphere(&mut rng) * 0.35;
+        tree.push(Elem::Sphere {
+            origin: vec3!(0.0, 1.1, 0.0) + offset,
+            radius: 0.3,
+            mat: Arc::new(leaves.clone()),
+        });
+    }
+    let blas = Arc::new(LinearBvh::new(BvhTree::new(tree, 0.0, 0.0)));
+
+    let mut objs: Vec<Elem> = vec![Elem::Sphere {
+        origin: vec3!(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) } }),
+    }];
+    for a in -50..50 {
+        for b in -50..50 {
+            let size = 0.6 + 0.8 * rng.f64();
+            let transform = Transform::scale(vec3!(size, size, size))
+                .then(&Transform::rotate_y(360.0 * rng.f64()))
+                .then(&Transform::translate(vec3!(a as f64 + 0.8 * rng.f64(), 0.0, b as f64 + 0.8 * rng.f64())));
+            objs.push(Elem::Instance { blas: blas.clone(), transform: Box::new(transform) });
+        }
+    }
+
+    let from = vec3!(13.0, 4.0, 3.0);
+    let at = vec3!(0.0, 0.0, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 30.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn motion_scene(width: u32, height: u32) -> Scene {
+    let mut objs: Vec<Elem> = vec![Elem::Sphere {
+        origin: vec3!(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) } }),
+    }];
+
+    // A propeller blade spinning through most of a half turn while the shutter is open
+    let blade = Elem::Sphere {
+        origin: vec3!(0.0, 0.0, 0.0),
+        radius: 1.0,
+        mat: Arc::new(Material::Metal { albedo: Texture::Solid { colour: vec3!(0.8, 0.8, 0.8) }, fuzz: 0.1 }),
+    };
+    let spin = |time: f64, degrees: f64| Keyframe {
+        time,
+        translation: vec3!(-1.5, 1.5, 0.0),
+        rotation: Quat::from_axis_angle(vec3!(0.0, 0.0, 1.0), degrees),
+        scale: vec3!(1.2, 0.1, 0.1),
+    };
+    objs.push(Elem::Animated {
+        obj: Box::new(blade),
+        motion: Box::new(AnimatedTransform::new(vec![spin(0.0, 0.0), spin(0.5, 80.0), spin(1.0, 160.0)])),
+    });
+
+    // A ball thrown along an arc, squashing as it lands
+    let ball = Elem::Sphere {
+        origin: vec3!(0.0, 0.0, 0.0),
+        radius: 0.5,
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.8, 0.2, 0.1) } }),
+    };
+    let arc = |time: f64, x: f64, y: f64, squash: f64| Keyframe {
+        time,
+        translation: vec3!(x, y, 0.0),
+        rotation: Quat::identity(),
+        scale: vec3!(1.0 / squash, squash, 1.0 / squash),
+    };
+    objs.push(Elem::Animated {
+        obj: Box::new(ball),
+        motion: Box::new(AnimatedTransform::new(vec![
+            arc(0.0, 0.5, 1.5, 1.0),
+            arc(0.4, 1.3, 2.0, 1.0),
+            arc(0.8, 2.1, 1.2, 1.0),
+            arc(1.0, 2.5, 0.35, 0.7),
+        ])),
+    });
+
+    let from = vec3!(0.0, 2.0, 10.0);
+    let at = vec3!(0.5, 1.2, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 35.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 1.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 1.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn whip_pan_scene(width: u32, height: u32) -> Scene {
+    let mut scene = weekend_scene(width, height);
+
+    // Whip from the metal sphere across to the diffuse one while dollying in and racking focus
+    let keys = vec![
+        CamKeyframe { time: 0.0, origin: vec3!(13.0, 2.0, 3.0), lookat: vec3!(4.0, 1.0, 0.0), vfov: 20.0, focus_dist: 9.5 },
+        CamKeyframe { time: 1.0, origin: vec3!(11.0, 2.0, 2.5), lookat: vec3!(-4.0, 1.0, 0.0), vfov: 25.0, focus_dist: 15.0 },
+    ];
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 20.0,
+        focus_dist: 10.0,
+        aperture: 0.1,
+    };
+    scene.cam = Camera::animated(keys, cs, 0.0, 1.0);
+    scene
+}
+
+#[allow(unused)]
+pub fn turntable_animation(width: u32, height: u32) -> Animation {
+    let mut statics: Vec<Elem> = vec![Elem::Sphere {
+        origin: vec3!(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        mat: Arc::new(Material::Lambertian {
+            albedo: Texture::Checker {
+                odd: Box::new(Texture::Solid { colour: vec3!(0.2, 0.3, 0.1) }),
+                even: Box::new(Texture::Solid { colour: vec3!(0.9, 0.9, 0.9) }),
+            }
+        }),
+    }];
+    for i in 0..8 {
+        let angle = i as f64 * std::f64::consts::FRAC_PI_4;
+        let mat = if i % 2 == 0 {
+            Material::Metal { albedo: Texture::Solid { colour: vec3!(0.7, 0.6, 0.5) }, fuzz: 0.05 }
+        } else {
+            Material::dielectric(1.5)
+        };
+        statics.push(Elem::Sphere {
+            origin: vec3!(3.0 * angle.cos(), 0.5, 3.0 * angle.sin()),
+            radius: 0.5,
+            mat: Arc::new(mat),
+        });
+    }
+
+    // A ball bouncing in the middle of the ring, once a second
+    let height_at = |t: f64| 0.5 + 2.0 * (t * std::f64::consts::PI).sin().abs();
+    let dynamics = move |time0: f64, time1: f64| vec![Elem::MovingSphere {
+        origin0: vec3!(0.0, height_at(time0), 0.0),
+        origin1: vec3!(0.0, height_at(time1), 0.0),
+        radius: 0.5,
+        time0,
+        time1,
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.8, 0.2, 0.1) } }),
+    }];
+
+    // One orbit every eight seconds
+    let orbit = |t: f64| {
+        let angle = t * std::f64::consts::PI / 4.0;
+        CamKeyframe {
+            time: t,
+            origin: vec3!(10.0 * angle.cos(), 3.0, 10.0 * angle.sin()),
+            lookat: vec3!(0.0, 1.0, 0.0),
+            vfov: 30.0,
+            focus_dist: 10.0,
+        }
+    };
+    let camera = move |time0: f64, time1: f64| {
+        let cs = CamSettings {
+            view_width: width,
+            view_height: height,
+            vfov: 30.0,
+            focus_dist: 10.0,
+            aperture: 0.0,
+        };
+        Camera::animated(vec![orbit(time0), orbit(time1)], cs, time0, time1)
+    };
+
+    Animation::new(statics, dynamics, camera, 24.0, 180.0, vec3!(0.5, 0.7, 1.0))
+}
+
+// The walls of a 555 unit Cornell box, open at the front
+fn cornell_walls() -> Vec<Elem> {
+    let red = Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.65, 0.05, 0.05) } });
+    let white = Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.73, 0.73, 0.73) } });
+    let green = Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.12, 0.45, 0.15) } });
+
+    let mut objs: Vec<Elem> = vec![
+        Elem::Quad { corner: vec3!(555.0, 0.0, 0.0), u: vec3!(0.0, 555.0, 0.0), v: vec3!(0.0, 0.0, 555.0), mat: green },
+        Elem::Quad { corner: vec3!(0.0, 0.0, 0.0), u: vec3!(0.0, 555.0, 0.0), v: vec3!(0.0, 0.0, 555.0), mat: red },
+        Elem::Quad { corner: vec3!(0.0, 0.0, 0.0), u: vec3!(555.0, 0.0, 0.0), v: vec3!(0.0, 0.0, 555.0), mat: white.clone() },
+        Elem::Quad { corner: vec3!(0.0, 0.0, 555.0), u: vec3!(555.0, 0.0, 0.0), v: vec3!(0.0, 555.0, 0.0), mat: white.clone() },
+    ];
+
+    // There are no emitters, so the sky lights the box through a hatch in the ceiling
+    let ceiling = [(0.0, 0.0, 213.0, 555.0), (343.0, 0.0, 555.0, 555.0), (213.0, 0.0, 343.0, 227.0), (213.0, 332.0, 343.0, 555.0)];
+    for (x0, z0, x1, z1) in ceiling {
+        objs.push(Elem::Quad {
+            corner: vec3!(x0, 555.0, z0),
+            u: vec3!(x1 - x0, 0.0, 0.0),
+            v: vec3!(0.0, 0.0, z1 - z0),
+            mat: white.clone(),
+        });
+    }
+    objs
+}
+
+// The tall and short boxes of the Cornell box, rotated into place
+fn cornell_boxes(tall_mat: Material, short_mat: Material) -> (Elem, Elem) {
+    let tall = Elem::cuboid(vec3!(0.0, 0.0, 0.0), vec3!(165.0, 330.0, 165.0), tall_mat);
+    let short = Elem::cuboid(vec3!(0.0, 0.0, 0.0), vec3!(165.0, 165.0, 165.0), short_mat);
+    (
+        Elem::Transformed {
+            obj: Box::new(tall),
+            transform: Box::new(Transform::rotate_y(15.0).then(&Transform::translate(vec3!(265.0, 0.0, 295.0)))),
+        },
+        Elem::Transformed {
+            obj: Box::new(short),
+            transform: Box::new(Transform::rotate_y(-18.0).then(&Transform::translate(vec3!(130.0, 0.0, 65.0)))),
+        },
+    )
+}
+
+fn cornell_camera(width: u32, height: u32) -> Camera {
+    let from = vec3!(278.0, 278.0, -800.0);
+    let at = vec3!(278.0, 278.0, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    Camera::new(from, at, cs, 0.0, 0.0)
+}
+
+#[allow(unused)]
+pub fn cornell_scene(width: u32, height: u32) -> Scene {
+    let white = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.73, 0.73, 0.73) } };
+    let mut objs = cornell_walls();
+    let (tall, short) = cornell_boxes(white.clone(), white);
+    objs.push(tall);
+    objs.push(short);
+
+    Scene {
+        cam: cornell_camera(width, height),
+        skybox_colour: vec3!(1.0, 1.0, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn cornell_smoke_scene(width: u32, height: u32) -> Scene {
+    let placeholder = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.0, 0.0, 0.0) } };
+    let mut objs = cornell_walls();
+    let (tall, short) = cornell_boxes(placeholder.clone(), placeholder);
+    objs.push(Elem::ConstantMedium {
+        boundary: Box::new(tall),
+        density: 0.01,
+        mat: Arc::new(Material::Medium { albedo: Texture::Solid { colour: vec3!(0.1, 0.1, 0.1) }, phase: PhaseFunction::Isotropic }),
+    });
+    objs.push(Elem::ConstantMedium {
+        boundary: Box::new(short),
+        density: 0.01,
+        mat: Arc::new(Material::Medium { albedo: Texture::Solid { colour: vec3!(0.9, 0.9, 0.9) }, phase: PhaseFunction::Isotropic }),
+    });
+
+    Scene {
+        cam: cornell_camera(width, height),
+        skybox_colour: vec3!(1.0, 1.0, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn quadrics_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+        Elem::Cylinder {
+            origin: vec3!(-3.0, 0.0, 0.0),
+            radius: 0.8,
+            height: 2.0,
+            mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.1, 0.2, 0.5) } }),
+        },
+        Elem::Cone {
+            origin: vec3!(-0.8, 0.0, -0.5),
+            radius: 0.9,
+            height: 2.2,
+            mat: Arc::new(Material::Metal { albedo: Texture::Solid { colour: vec3!(0.8, 0.6, 0.2) }, fuzz: 0.1 }),
+        },
+        Elem::Disk {
+            center: vec3!(3.5, 1.2, -1.0),
+            normal: vec3!(-1.0, 0.3, 1.0),
+            radius: 1.1,
+            mat: Arc::new(Material::Metal { albedo: Texture::Solid { colour: vec3!(0.9, 0.9, 0.9) }, fuzz: 0.0 }),
+        },
+    ];
+
+    // Stand the torus up on its rim, facing the camera
+    let torus = Elem::Torus {
+        origin: vec3!(0.0, 0.0, 0.0),
+        major: 0.8,
+        minor: 0.3,
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.7, 0.2, 0.2) } }),
+    };
+    objs.push(Elem::Transformed {
+        obj: Box::new(torus),
+        transform: Box::new(Transform::rotate_x(70.0).then(&Transform::translate(vec3!(1.3, 1.1, 0.8)))),
+    });
+
+    let from = vec3!(0.0, 3.0, 10.0);
+    let at = vec3!(0.0, 1.0, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn cloud_scene(width: u32, height: u32) -> Scene {
+    let mut objs: Vec<Elem> = vec![Elem::Sphere {
+        origin: vec3!(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.4, 0.5, 0.3) } }),
+    }];
+
+    // A billowing cloud from procedural noise
+    let cloud = Elem::Sphere { origin: vec3!(-1.5, 2.5, 0.0), radius: 1.5, mat: Arc::new(Material::dielectric(1.0)) };
+    objs.push(Elem::HeterogeneousMedium {
+        boundary: Box::new(cloud),
+        density: Box::new(Density::Perlin { noise: PerlinNoise::default(), frequency: 1.5, scale: 4.0 }),
+        // Water droplets scatter strongly forwards with a faint backward glow
+        mat: Arc::new(Material::Medium {
+            albedo: Texture::Solid { colour: vec3!(0.95, 0.95, 0.95) },
+            phase: PhaseFunction::DoubleHenyeyGreenstein { g_forward: 0.8, g_back: -0.3, weight: 0.9 },
+        }),
+    });
+
+    // A smoke plume voxelised from a column that spreads and thins as it rises
+    let dims = [32, 64, 32];
+    let mut data = Vec::with_capacity(dims[0] * dims[1] * dims[2]);
+    for z in 0..dims[2] {
+        for y in 0..dims[1] {
+            for x in 0..dims[0] {
+                let rise = y as f64 / dims[1] as f64;
+                let dx = (x as f64 + 0.5) / dims[0] as f64 - 0.5 - 0.15 * (rise * 6.0).sin() * rise;
+                let dz = (z as f64 + 0.5) / dims[2] as f64 - 0.5;
+                let spread = 0.05 + 0.15 * rise;
+                let d = (-(dx * dx + dz * dz) / (2.0 * spread * spread)).exp() * (1.0 - rise);
+                data.push(d as f32);
+            }
+        }
+    }
+    let bounds = Aabb { min: vec3!(1.0, 0.0, -1.0), max: vec3!(3.0, 4.0, 1.0) };
+    let plume = Elem::cuboid(bounds.min, bounds.max, Material::dielectric(1.0));
+    objs.push(Elem::HeterogeneousMedium {
+        boundary: Box::new(plume),
+        density: Box::new(Density::Grid { grid: VoxelGrid::new(dims, data, bounds).expect("the plume fills its grid"), scale: 6.0 }),
+        mat: Arc::new(Material::Medium {
+            albedo: Texture::Solid { colour: vec3!(0.3, 0.3, 0.3) },
+            phase: PhaseFunction::HenyeyGreenstein { g: 0.4 },
+        }),
+    });
+
+    let from = vec3!(0.0, 2.0, 12.0);
+    let at = vec3!(0.5, 2.0, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 35.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn coloured_glass_scene(width: u32, height: u32) -> Scene {
+    let mut objs: Vec<Elem> = vec![Elem::Sphere {
+        origin: vec3!(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        mat: Arc::new(Material::Lambertian {
+            albedo: Texture::Checker {
+                odd: Box::new(Texture::Solid { colour: vec3!(0.2, 0.2, 0.2) }),
+                even: Box::new(Texture::Solid { colour: vec3!(0.9, 0.9, 0.9) }),
+            }
+        }),
+    }];
+
+    // Absorption is per unit distance, so thicker glass is more deeply coloured
+    let bottle_green = HomogeneousMedium { absorption: vec3!(1.2, 0.1, 0.9), scattering: vec3!(0.0, 0.0, 0.0), phase: PhaseFunction::Isotropic };
+    let ruby = HomogeneousMedium { absorption: vec3!(0.05, 2.5, 1.8), scattering: vec3!(0.0, 0.0, 0.0), phase: PhaseFunction::Isotropic };
+    let opal = HomogeneousMedium {
+        absorption: vec3!(0.3, 0.15, 0.05),
+        scattering: vec3!(4.0, 4.0, 4.0),
+        phase: PhaseFunction::HenyeyGreenstein { g: 0.3 },
+    };
+    let cobalt = HomogeneousMedium { absorption: vec3!(2.0, 1.4, 0.1), scattering: vec3!(0.0, 0.0, 0.0), phase: PhaseFunction::Isotropic };
+
+    objs.push(Elem::Sphere {
+        origin: vec3!(-2.2, 1.0, 0.0),
+        radius: 1.0,
+        mat: Arc::new(Material::Dielectric(Dielectric { interior: Some(bottle_green), ..Dielectric::new(1.5) })),
+    });
+    objs.push(Elem::Sphere {
+        origin: vec3!(0.0, 1.0, 0.0),
+        radius: 1.0,
+        mat: Arc::new(Material::Dielectric(Dielectric { interior: Some(ruby), ..Dielectric::new(1.76) })),
+    });
+    objs.push(Elem::Sphere {
+        origin: vec3!(2.2, 1.0, 0.0),
+        radius: 1.0,
+        mat: Arc::new(Material::Dielectric(Dielectric { interior: Some(opal), ..Dielectric::new(1.45) })),
+    });
+    objs.push(Elem::cuboid(vec3!(-0.6, 0.0, 1.6), vec3!(0.6, 0.6, 2.4), Material::Dielectric(Dielectric { interior: Some(cobalt), ..Dielectric::new(1.5) })));
+
+    let from = vec3!(0.0, 3.0, 9.0);
+    let at = vec3!(0.0, 0.8, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 35.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn drink_scene(width: u32, height: u32) -> Scene {
+    let mut objs: Vec<Elem> = vec![Elem::Sphere {
+        origin: vec3!(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        mat: Arc::new(Material::Lambertian {
+            albedo: Texture::Checker {
+                odd: Box::new(Texture::Solid { colour: vec3!(0.2, 0.2, 0.2) }),
+                even: Box::new(Texture::Solid { colour: vec3!(0.9, 0.9, 0.9) }),
+            }
+        }),
+    }];
+
+    // Each medium overlaps the one it sits in and wins the overlap by priority, so there are
+    // no air gaps between the glass, the drink and the ice
+    let glass = Material::Dielectric(Dielectric { priority: 1, ..Dielectric::new(1.5) });
+    let hollow = Material::Dielectric(Dielectric { priority: 2, ..Dielectric::new(1.0) });
+    let juice = HomogeneousMedium { absorption: vec3!(0.1, 0.6, 1.5), scattering: vec3!(0.0, 0.0, 0.0), phase: PhaseFunction::Isotropic };
+    let drink = Material::Dielectric(Dielectric { interior: Some(juice), priority: 3, ..Dielectric::new(1.33) });
+    let ice = Material::Dielectric(Dielectric { priority: 4, ..Dielectric::new(1.31) });
+
+    objs.push(Elem::Cylinder { origin: vec3!(0.0, 0.0, 0.0), radius: 1.0, height: 2.5, mat: Arc::new(glass) });
+    objs.push(Elem::Cylinder { origin: vec3!(0.0, 0.2, 0.0), radius: 0.9, height: 2.6, mat: Arc::new(hollow) });
+    objs.push(Elem::Cylinder { origin: vec3!(0.0, 0.19, 0.0), radius: 0.91, height: 1.5, mat: Arc::new(drink) });
+
+    let cube = Elem::cuboid(vec3!(-0.3, -0.3, -0.3), vec3!(0.3, 0.3, 0.3), ice);
+    objs.push(Elem::Transformed {
+        obj: Box::new(cube),
+        transform: Box::new(Transform::rotate(vec3!(1.0, 1.0, 0.0), 30.0).then(&Transform::translate(vec3!(0.1, 1.6, 0.1)))),
+    });
+
+    let from = vec3!(0.0, 3.0, 8.0);
+    let at = vec3!(0.0, 1.2, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 30.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn metals_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.4, 0.4, 0.4) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+    ];
+
+    // Each metal from polished at the front to rough at the back
+    let metals = [ConductorPreset::Gold, ConductorPreset::Copper, ConductorPreset::Aluminium, ConductorPreset::Silver];
+    for (i, metal) in metals.into_iter().enumerate() {
+        for (j, roughness) in [0.05, 0.3, 0.6].into_iter().enumerate() {
+            objs.push(Elem::Sphere {
+                origin: vec3!(-3.3 + 2.2 * i as f64, 0.8, 1.0 - 2.2 * j as f64),
+                radius: 0.8,
+                mat: Arc::new(Material::conductor(metal, roughness)),
+            });
+        }
+    }
+
+    // Brushed aluminium, rough across the tangent and smooth along it
+    objs.push(Elem::Sphere {
+        origin: vec3!(0.0, 0.6, 3.4),
+        radius: 0.6,
+        mat: Arc::new(Material::anisotropic_conductor(ConductorPreset::Aluminium, 0.15, 0.6)),
+    });
+
+    let from = vec3!(0.0, 5.0, 12.0);
+    let at = vec3!(0.0, 0.5, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 12.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn frosted_glass_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+    ];
+
+    // Coloured stripes behind the glass show how much each surface blurs what's beyond it
+    let stripes = [vec3!(0.8, 0.1, 0.1), vec3!(0.9, 0.8, 0.1), vec3!(0.1, 0.6, 0.2), vec3!(0.1, 0.2, 0.8)];
+    for (i, colour) in stripes.into_iter().enumerate() {
+        objs.push(Elem::Quad {
+            corner: vec3!(-6.0 + 3.0 * i as f64, 0.0, -3.0),
+            u: vec3!(1.5, 0.0, 0.0),
+            v: vec3!(0.0, 4.0, 0.0),
+            mat: Arc::new(Material::Lambertian { albedo: Texture::Solid { colour } }),
+        });
+    }
+
+    // Smooth to frosted from left to right
+    for (i, roughness) in [0.0, 0.15, 0.35, 0.6].into_iter().enumerate() {
+        objs.push(Elem::Sphere {
+            origin: vec3!(-3.3 + 2.2 * i as f64, 0.9, 0.0),
+            radius: 0.9,
+            mat: Arc::new(Material::Dielectric(Dielectric { roughness, ..Dielectric::new(1.5) })),
+        });
+    }
+
+    // A sandblasted panel and a block of rough ice in front
+    objs.push(Elem::cuboid(
+        vec3!(-3.0, 0.0, 2.0),
+        vec3!(-0.5, 1.8, 2.1),
+        Material::Dielectric(Dielectric { roughness: 0.3, ..Dielectric::new(1.5) }),
+    ));
+    objs.push(Elem::cuboid(
+        vec3!(1.0, 0.0, 1.8),
+        vec3!(2.2, 1.2, 3.0),
+        Material::Dielectric(Dielectric { roughness: 0.2, ..Dielectric::new(1.31) }),
+    ));
+
+    let from = vec3!(0.0, 3.0, 11.0);
+    let at = vec3!(0.0, 1.0, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 11.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn principled_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+    ];
+
+    // Plastic to metal along the back row, each getting rougher
+    let red = Texture::Solid { colour: vec3!(0.8, 0.1, 0.1) };
+    for i in 0..5 {
+        let t = i as f64 / 4.0;
+        let mat = Principled {
+            metallic: Texture::constant(t),
+            roughness: Texture::constant(0.1 + 0.5 * t),
+            ..Principled::new(red.clone())
+        };
+        objs.push(Elem::Sphere { origin: vec3!(-4.4 + 2.2 * i as f64, 0.9, -1.5), radius: 0.9, mat: Arc::new(Material::Principled(Box::new(mat))) });
+    }
+
+    // Car paint, velvet and tinted glass along the front
+    let paint = Principled {
+        roughness: Texture::constant(0.4),
+        clearcoat: Texture::constant(1.0),
+        ..Principled::new(Texture::Solid { colour: vec3!(0.05, 0.15, 0.5) })
+    };
+    let velvet = Principled {
+        roughness: Texture::constant(1.0),
+        specular: Texture::constant(0.0),
+        sheen: Texture::Solid { colour: vec3!(0.6, 0.4, 0.6) },
+        ..Principled::new(Texture::Solid { colour: vec3!(0.3, 0.05, 0.3) })
+    };
+    let glass = Principled {
+        roughness: Texture::constant(0.05),
+        transmission: Texture::constant(1.0),
+        ..Principled::new(Texture::Solid { colour: vec3!(0.7, 0.9, 0.8) })
+    };
+    // A packed map in the glTF layout, roughness in green and metallic in blue
+    let packed = Texture::Checker {
+        odd: Box::new(Texture::Solid { colour: vec3!(0.0, 0.6, 0.0) }),
+        even: Box::new(Texture::Solid { colour: vec3!(0.0, 0.1, 1.0) }),
+    };
+    let mapped = Principled {
+        roughness: Texture::Channel { tex: Box::new(packed.clone()), channel: 1 },
+        metallic: Texture::Channel { tex: Box::new(packed), channel: 2 },
+        ..Principled::new(Texture::Solid { colour: vec3!(0.9, 0.7, 0.3) })
+    };
+    for (i, mat) in [paint, velvet, glass, mapped].into_iter().enumerate() {
+        objs.push(Elem::Sphere { origin: vec3!(-3.3 + 2.2 * i as f64, 0.9, 1.5), radius: 0.9, mat: Arc::new(Material::Principled(Box::new(mat))) });
+    }
+
+    let from = vec3!(0.0, 4.0, 12.0);
+    let at = vec3!(0.0, 0.7, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 12.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn coated_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+    ];
+
+    let coat = |base: Material, roughness: f64| Material::Coated { base: Box::new(base), ir: 1.5, roughness };
+
+    // Glossy plastic, from polished to satin
+    let plastic = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.1, 0.5, 0.1) } };
+    for (i, roughness) in [0.02, 0.15, 0.4].into_iter().enumerate() {
+        objs.push(Elem::Sphere { origin: vec3!(-2.2 + 2.2 * i as f64, 0.9, -1.5), radius: 0.9, mat: Arc::new(coat(plastic.clone(), roughness)) });
+    }
+
+    // Varnished grain, metallic car paint and lacquered glass
+    let grain = Material::Lambertian { albedo: Texture::Perlin { noise: PerlinNoise::default(), scale: 4.0 } };
+    let flakes = Material::Metal { albedo: Texture::Solid { colour: vec3!(0.6, 0.1, 0.1) }, fuzz: 0.4 };
+    let glass = Material::dielectric(1.5);
+    for (i, base) in [grain, flakes, glass].into_iter().enumerate() {
+        objs.push(Elem::Sphere { origin: vec3!(-2.2 + 2.2 * i as f64, 0.9, 1.5), radius: 0.9, mat: Arc::new(coat(base, 0.02)) });
+    }
+
+    let from = vec3!(0.0, 4.0, 12.0);
+    let at = vec3!(0.0, 0.7, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 35.0,
+        focus_dist: 12.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn rough_diffuse_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.5, 0.5, 0.5) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+    ];
+
+    // Clay from Lambertian smooth to very rough
+    let clay = Texture::Solid { colour: vec3!(0.7, 0.45, 0.3) };
+    for (i, sigma) in [0.0, 0.3, 0.6, 1.0].into_iter().enumerate() {
+        objs.push(Elem::Sphere {
+            origin: vec3!(-3.3 + 2.2 * i as f64, 0.9, 0.0),
+            radius: 0.9,
+            mat: Arc::new(Material::OrenNayar { albedo: clay.clone(), sigma: Texture::constant(sigma) }),
+        });
+    }
+
+    // Concrete with patches of differing roughness
+    let patches = Texture::Checker {
+        odd: Box::new(Texture::constant(0.2)),
+        even: Box::new(Texture::constant(0.9)),
+    };
+    objs.push(Elem::cuboid(
+        vec3!(-1.5, 0.0, 2.0),
+        vec3!(1.5, 1.0, 3.0),
+        Material::OrenNayar { albedo: Texture::Solid { colour: vec3!(0.6, 0.6, 0.58) }, sigma: patches },
+    ));
+
+    let from = vec3!(0.0, 3.0, 12.0);
+    let at = vec3!(0.0, 0.8, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 35.0,
+        focus_dist: 12.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn mix_scene(width: u32, height: u32) -> Scene {
+    // Dirt streaked over polished tiles
+    let tiles = Material::Metal { albedo: Texture::Solid { colour: vec3!(0.85, 0.85, 0.8) }, fuzz: 0.05 };
+    let dirt = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.3, 0.22, 0.15) } };
+    let grime = Texture::Perlin { noise: PerlinNoise::default(), scale: 1.0 };
+    let mut objs: Vec<Elem> = vec![Elem::Plane {
+        point: vec3!(0.0, 0.0, 0.0),
+        normal: vec3!(0.0, 1.0, 0.0),
+        mat: Arc::new(Material::Mix { first: Box::new(tiles), second: Box::new(dirt), mask: grime }),
+    }];
+
+    // Rust eating through blue paint in noisy patches
+    let paint = Material::Coated {
+        base: Box::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.1, 0.25, 0.6) } }),
+        ir: 1.5,
+        roughness: 0.1,
+    };
+    let rust = Material::OrenNayar { albedo: Texture::Solid { colour: vec3!(0.45, 0.2, 0.08) }, sigma: Texture::constant(0.8) };
+    let patches = Texture::Perlin { noise: PerlinNoise::default(), scale: 3.0 };
+    objs.push(Elem::Sphere {
+        origin: vec3!(-1.2, 1.0, 0.0),
+        radius: 1.0,
+        mat: Arc::new(Material::Mix { first: Box::new(paint), second: Box::new(rust), mask: patches }),
+    });
+
+    // A half and half blend of gold and diffuse white
+    let gold = Material::conductor(ConductorPreset::Gold, 0.2);
+    let white = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.9, 0.9, 0.9) } };
+    objs.push(Elem::Sphere {
+        origin: vec3!(1.2, 1.0, 0.0),
+        radius: 1.0,
+        mat: Arc::new(Material::Mix { first: Box::new(gold), second: Box::new(white), mask: Texture::constant(0.5) }),
+    });
+
+    let from = vec3!(0.0, 3.0, 9.0);
+    let at = vec3!(0.0, 0.8, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 35.0,
+        focus_dist: 9.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn iridescence_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.05, 0.05, 0.05) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+    ];
+
+    // Soap bubbles, a film of water around air, from thin to thick
+    for (i, thickness) in [250.0, 400.0, 650.0].into_iter().enumerate() {
+        let film = ThinFilm { ir: 1.33, thickness };
+        objs.push(Elem::Sphere {
+            origin: vec3!(-2.4 + 2.4 * i as f64, 2.8, -1.0),
+            radius: 1.0,
+            mat: Arc::new(Material::Dielectric(Dielectric { film: Some(film), ..Dielectric::new(1.0) })),
+        });
+    }
+
+    // An oil slick over a puddle
+    let oil = ThinFilm { ir: 1.45, thickness: 500.0 };
+    objs.push(Elem::cuboid(
+        vec3!(-4.0, 0.0, 1.5),
+        vec3!(4.0, 0.02, 4.0),
+        Material::Dielectric(Dielectric { film: Some(oil), ..Dielectric::new(1.33) }),
+    ));
+
+    // Dense flint glass, splitting the horizon behind it into colours
+    let flint = Material::Dielectric(Dielectric { dispersion: Some(Dispersion::SF11), ..Dielectric::new(1.78) });
+    objs.push(Elem::Sphere { origin: vec3!(-1.5, 0.9, 0.5), radius: 0.9, mat: Arc::new(flint.clone()) });
+    objs.push(Elem::Transformed {
+        obj: Box::new(Elem::cuboid(vec3!(-0.7, -0.7, -0.7), vec3!(0.7, 0.7, 0.7), flint)),
+        transform: Box::new(Transform::rotate_z(45.0).then(&Transform::rotate_y(30.0)).then(&Transform::translate(vec3!(1.5, 1.0, 0.5)))),
+    });
+
+    let from = vec3!(0.0, 2.5, 10.0);
+    let at = vec3!(0.0, 1.5, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 10.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+#[allow(unused)]
+pub fn subsurface_scene(width: u32, height: u32) -> Scene {
+    let ground = Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.3, 0.3, 0.3) } };
+    let mut objs: Vec<Elem> = vec![
+        Elem::Plane { point: vec3!(0.0, 0.0, 0.0), normal: vec3!(0.0, 1.0, 0.0), mat: Arc::new(ground) },
+    ];
+
+    // Skin lets red travel furthest, marble and milk are bright and dense, jade is deep and clear
+    let skin = Material::subsurface(vec3!(0.85, 0.55, 0.45), vec3!(0.25, 0.17, 0.12), 1.4, 0.3);
+    let marble = Material::subsurface(vec3!(0.93, 0.92, 0.88), vec3!(0.12, 0.12, 0.12), 1.5, 0.05);
+    let milk = Material::subsurface(vec3!(0.95, 0.94, 0.9), vec3!(0.06, 0.06, 0.06), 1.35, 0.0);
+    let jade = Material::subsurface(vec3!(0.35, 0.8, 0.45), vec3!(0.6, 0.9, 0.6), 1.6, 0.02);
+    for (i, mat) in [skin, marble, milk, jade].into_iter().enumerate() {
+        objs.push(Elem::Sphere { origin: vec3!(-3.3 + 2.2 * i as f64, 0.9, 0.0), radius: 0.9, mat: Arc::new(mat) });
+    }
+
+    // A thin marble slab shows light bleeding through its edges
+    objs.push(Elem::cuboid(
+        vec3!(-2.0, 0.0, 2.0),
+        vec3!(2.0, 0.15, 3.0),
+        Material::subsurface(vec3!(0.93, 0.92, 0.88), vec3!(0.12, 0.12, 0.12), 1.5, 0.05),
+    ));
+
+    let from = vec3!(0.0, 3.0, 12.0);
+    let at = vec3!(0.0, 0.8, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 35.0,
+        focus_dist: 12.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+// A normal map of square tiles with bevelled edges, as an image would store it
+fn tile_normal_map(size: u32, tiles: u32) -> image::RgbImage {
+    let bevel = 0.15;
+    image::RgbImage::from_fn(size, size, |x, y| {
+        // Position within the tile, with v running up the image
+        let fx = (x as f64 * tiles as f64 / size as f64).fract();
+        let fy = 1.0 - (y as f64 * tiles as f64 / size as f64).fract();
+        let slope = |f: f64| if f < bevel { -1.0 } else if f > 1.0 - bevel { 1.0 } else { 0.0 };
+        let n = vec3!(slope(fx), slope(fy), 1.5).unit();
+        image::Rgb([0, 1, 2].map(|c| ((n[c] * 0.5 + 0.5) * 255.0) as u8))
+    })
+}
+
+#[allow(unused)]
+pub fn bump_scene(width: u32, height: u32) -> Scene {
+    // One tile repeated across the floor
+    let tile = ImageTexture::new(&tile_normal_map(64, 1), ColourSpace::Linear)
+        .with_wrap(Wrap::Repeat)
+        .with_transform(UvTransform { scale: (8.0, 8.0), ..UvTransform::identity() });
+    let tiles = Texture::Image { img: tile };
+    let floor = Material::Bumped {
+        base: Box::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.6, 0.55, 0.5) } }),
+        map: SurfaceMap::Normal { map: tiles.clone(), strength: 1.0 },
+    };
+    let mut objs: Vec<Elem> = vec![Elem::Quad {
+        corner: vec3!(-6.0, 0.0, 6.0),
+        u: vec3!(12.0, 0.0, 0.0),
+        v: vec3!(0.0, 0.0, -12.0),
+        mat: Arc::new(floor),
+    }];
+
+    // Stucco from noise, hammered gold, and glass rippled by the same noise
+    let noise = Texture::Perlin { noise: PerlinNoise::default(), scale: 8.0 };
+    let stucco = Material::Bumped {
+        base: Box::new(Material::Lambertian { albedo: Texture::Solid { colour: vec3!(0.8, 0.4, 0.2) } }),
+        map: SurfaceMap::Bump { height: noise.clone(), scale: 0.03 },
+    };
+    let hammered = Material::Bumped {
+        base: Box::new(Material::conductor(ConductorPreset::Gold, 0.15)),
+        map: SurfaceMap::Normal { map: tiles, strength: 0.6 },
+    };
+    let rippled = Material::Bumped {
+        base: Box::new(Material::dielectric(1.5)),
+        map: SurfaceMap::Bump { height: noise, scale: 0.02 },
+    };
+    for (i, mat) in [stucco, hammered, rippled].into_iter().enumerate() {
+        objs.push(Elem::Sphere { origin: vec3!(-2.4 + 2.4 * i as f64, 1.0, 0.0), radius: 1.0, mat: Arc::new(mat) });
+    }
+
+    let from = vec3!(0.0, 3.5, 9.0);
+    let at = vec3!(0.0, 0.8, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 9.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
+    }
+}
+
+// A 4x4 checker shaded red along u and blue along v, so flips and turns are easy to see
+fn test_card(size: u32) -> image::RgbImage {
+    image::RgbImage::from_fn(size, size, |x, y| {
+        let (u, v) = (x as f64 / size as f64, 1.0 - y as f64 / size as f64);
+        let check = if (x * 4 / size + y * 4 / size).is_multiple_of(2) { 0.9 } else { 0.3 };
+        let c = vec3!(check * (0.4 + 0.6 * u), check * 0.7, check * (0.4 + 0.6 * v));
+        image::Rgb([0, 1, 2].map(|i| (c[i] * 255.0) as u8))
+    })
+}
+
+#[allow(unused)]
+pub fn texture_scene(width: u32, height: u32) -> Scene {
+    let card = ImageTexture::new(&test_card(64), ColourSpace::Linear);
+    // Each panel shows uvs from -0.5 to 1.5, except the last which is turned and tiled
+    let outside = UvTransform { scale: (2.0, 2.0), rotation: 0.0, offset: (-0.5, -0.5) };
+    let panels = [
+        card.clone().with_wrap(Wrap::Repeat).with_transform(outside),
+        card.clone().with_wrap(Wrap::Mirror).with_transform(outside),
+        card.clone().with_wrap(Wrap::Clamp).with_transform(outside),
+        card.clone().with_wrap(Wrap::Border(vec3!(0.1, 0.1, 0.1))).with_transform(outside),
+        card.clone()
+            .with_wrap(Wrap::Repeat)
+            .with_filter(Filter::Nearest)
+            .with_transform(UvTransform { scale: (3.0, 3.0), rotation: 30.0, offset: (0.25, 0.0) }),
+    ];
+    let mut objs: Vec<Elem> = panels.into_iter().enumerate().map(|(i, img)| Elem::Quad {
+        corner: vec3!(-5.5 + 2.2 * i as f64, 0.5, 0.0),
+        u: vec3!(2.0, 0.0, 0.0),
+        v: vec3!(0.0, 2.0, 0.0),
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Image { img } }),
+    }).collect();
+
+    // A floor tiled far into the distance
+    let floor = card.with_wrap(Wrap::Repeat).with_transform(UvTransform { scale: (40.0, 40.0), ..UvTransform::identity() });
+    objs.push(Elem::Quad {
+        corner: vec3!(-20.0, 0.0, 20.0),
+        u: vec3!(40.0, 0.0, 0.0),
+        v: vec3!(0.0, 0.0, -40.0),
+        mat: Arc::new(Material::Lambertian { albedo: Texture::Image { img: floor } }),
+    });
+
+    let from = vec3!(0.0, 3.0, 16.0);
+    let at = vec3!(0.0, 1.2, 0.0);
+
+    let cs = CamSettings {
+        view_width: width,
+        view_height: height,
+        vfov: 40.0,
+        focus_dist: 16.0,
+        aperture: 0.0,
+    };
+    let cam = Camera::new(from, at, cs, 0.0, 0.0);
+    Scene {
+        cam,
+        skybox_colour: vec3!(0.5, 0.7, 1.0),
+        bvh: LinearBvh::new(BvhTree::new(objs, 0.0, 0.0)),
     }
 }
@@ -0,0 +1,286 @@
+use std::{f64::consts::PI, sync::OnceLock};
+
+use fastrand::Rng;
+
+use crate::math::{Mat4, Vec3, vec3};
+
+// The visible range wavelengths are sampled from, in nanometres
+pub const MIN_WAVELENGTH: f64 = 380.0;
+pub const MAX_WAVELENGTH: f64 = 780.0;
+
+// Representative wavelengths for the red, green and blue channels
+pub const RGB_WAVELENGTHS: [f64; 3] = [650.0, 532.0, 450.0];
+
+pub fn sample_wavelength(rng: &mut Rng) -> f64 {
+    MIN_WAVELENGTH + (MAX_WAVELENGTH - MIN_WAVELENGTH) * rng.f64()
+}
+
+// Piecewise gaussian fit of the CIE 1931 matching functions (Wyman, Sloan and Shirley 2013)
+fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu: f64, lo: f64, hi: f64| {
+        let t = (lambda - mu) / if lambda < mu { lo } else { hi };
+        (-0.5 * t * t).exp()
+    };
+    let x = 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    vec3!(x, y, z)
+}
+
+// Linear sRGB. Pure spectral colours lie outside the gamut, and their negative lobes are kept as
+// they cancel where the matching functions overlap once spectra are integrated
+fn unnormalised_rgb(lambda: f64) -> Vec3 {
+    let c = cie_xyz(lambda);
+    let r = 3.2406 * c[0] - 1.5372 * c[1] - 0.4986 * c[2];
+    let g = -0.9689 * c[0] + 1.8758 * c[1] + 0.0415 * c[2];
+    let b = 0.0557 * c[0] - 0.2040 * c[1] + 1.0570 * c[2];
+    vec3!(r, g, b)
+}
+
+// The average over the sampled range of wavelengths
+fn spectral_mean(f: impl Fn(f64) -> Vec3) -> Vec3 {
+    let steps = 4000;
+    let sum = (0..steps).fold(vec3!(0.0, 0.0, 0.0), |acc, i| {
+        acc + f(MIN_WAVELENGTH + (MAX_WAVELENGTH - MIN_WAVELENGTH) * (i as f64 + 0.5) / steps as f64)
+    });
+    sum / steps as f64
+}
+
+/// The colour a single wavelength contributes, such that averaging over uniformly sampled
+/// wavelengths turns a spectrum from `rgb_to_spectrum` back into the colour it came from, and a
+/// flat spectrum into white. Multiplying a path's throughput by it turns it spectral.
+pub fn wavelength_rgb(lambda: f64) -> Vec3 {
+    static TO_RGB: OnceLock<Mat4> = OnceLock::new();
+    let to_rgb = TO_RGB.get_or_init(|| {
+        // Undo what the matching functions make of the red, green and blue spectra
+        let mut primaries = Mat4::identity();
+        for (c, basis) in [SMITS_RED, SMITS_GREEN, SMITS_BLUE].iter().enumerate() {
+            let rgb = spectral_mean(|l| unnormalised_rgb(l) * basis[smits_bin(l)]);
+            for r in 0..3 {
+                primaries.elems[r][c] = rgb[r];
+            }
+        }
+        let mut to_rgb = primaries.inverse().expect("the primary spectra are independent");
+
+        // The primaries don't quite sum to a flat spectrum, so rescale for white to stay neutral
+        let white = to_rgb.mul_vector(&spectral_mean(unnormalised_rgb));
+        for r in 0..3 {
+            for c in 0..3 {
+                to_rgb.elems[r][c] /= white[r];
+            }
+        }
+        to_rgb
+    });
+    to_rgb.mul_vector(&unnormalised_rgb(lambda))
+}
+
+/// Reflectance of a film of index `film_ir` and `thickness` nanometres between media of index
+/// n1 (the incident side) and n2, from the interference of its two surfaces' reflections.
+pub fn thin_film_reflectance(cos_i: f64, n1: f64, film_ir: f64, n2: f64, thickness: f64, lambda: f64) -> f64 {
+    let cos_1 = cos_i.clamp(0.0, 1.0);
+    let sin_sq_1 = 1.0 - cos_1 * cos_1;
+
+    // Snell's law into the film then on into the far medium, totally reflecting if either fails
+    let sin_sq_f = (n1 / film_ir).powi(2) * sin_sq_1;
+    let sin_sq_2 = (n1 / n2).powi(2) * sin_sq_1;
+    if sin_sq_f >= 1.0 || sin_sq_2 >= 1.0 {
+        return 1.0;
+    }
+    let cos_f = (1.0 - sin_sq_f).sqrt();
+    let cos_2 = (1.0 - sin_sq_2).sqrt();
+
+    // Phase difference picked up crossing the film and back
+    let cos_delta = (4.0 * PI * film_ir * thickness * cos_f / lambda).cos();
+    let airy = |r12: f64, r23: f64| {
+        let cross = 2.0 * r12 * r23 * cos_delta;
+        (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+    };
+
+    let rs = airy(
+        (n1 * cos_1 - film_ir * cos_f) / (n1 * cos_1 + film_ir * cos_f),
+        (film_ir * cos_f - n2 * cos_2) / (film_ir * cos_f + n2 * cos_2),
+    );
+    let rp = airy(
+        (film_ir * cos_1 - n1 * cos_f) / (film_ir * cos_1 + n1 * cos_f),
+        (n2 * cos_f - film_ir * cos_2) / (n2 * cos_f + film_ir * cos_2),
+    );
+    0.5 * (rs + rp)
+}
+
+// Smits' basis spectra for upsampling RGB reflectances, in ten bins spanning 380nm to 720nm
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// Longer wavelengths than the last bin carry on with it
+fn smits_bin(lambda: f64) -> usize {
+    (((lambda - 380.0) / 34.0).floor().max(0.0) as usize).min(9)
+}
+
+/// A smooth spectrum matching an RGB colour (Smits 1999), evaluated at one wavelength. White
+/// upsamples to a flat spectrum, so uncoloured light and surfaces stay neutral.
+pub fn rgb_to_spectrum(rgb: &Vec3, lambda: f64) -> f64 {
+    let bin = smits_bin(lambda);
+    let (r, g, b) = (rgb[0], rgb[1], rgb[2]);
+
+    // Take out as much white as possible, then the secondary and primary colours of what's left
+    if r <= g && r <= b {
+        let rest = if g <= b {
+            (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        };
+        r * SMITS_WHITE[bin] + rest
+    } else if g <= r && g <= b {
+        let rest = if r <= b {
+            (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        };
+        g * SMITS_WHITE[bin] + rest
+    } else {
+        let rest = if r <= g {
+            (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        };
+        b * SMITS_WHITE[bin] + rest
+    }
+}
+
+// How many wavelengths a spectral path carries
+pub const HERO_COUNT: usize = 4;
+
+/// The light a path still carries. Spectral paths follow a hero wavelength and others spaced
+/// evenly around the visible range from it, every RGB factor is upsampled at each of them.
+#[derive(Debug, Clone, Copy)]
+pub enum Throughput {
+    Rgb(Vec3),
+    Spectral { lambdas: [f64; HERO_COUNT], values: [f64; HERO_COUNT], terminated: bool },
+}
+
+impl Throughput {
+    pub fn spectral(rng: &mut Rng) -> Self {
+        let hero = sample_wavelength(rng);
+        let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+        let lambdas = std::array::from_fn(|i| MIN_WAVELENGTH + (hero - MIN_WAVELENGTH + range * i as f64 / HERO_COUNT as f64) % range);
+        Self::Spectral { lambdas, values: [1.0; HERO_COUNT], terminated: false }
+    }
+
+    pub fn hero_wavelength(&self) -> Option<f64> {
+        match self {
+            Self::Rgb(_) => None,
+            Self::Spectral { lambdas, .. } => Some(lambdas[0]),
+        }
+    }
+
+    pub fn scale(&mut self, rgb: &Vec3) {
+        match self {
+            Self::Rgb(colour) => *colour = *colour * *rgb,
+            Self::Spectral { lambdas, values, .. } => {
+                for (v, l) in values.iter_mut().zip(lambdas.iter()) {
+                    *v *= rgb_to_spectrum(rgb, *l);
+                }
+            },
+        }
+    }
+
+    // Once the path has followed one wavelength the others can't come along, so the hero carries
+    // their share
+    pub fn terminate_secondary(&mut self) {
+        if let Self::Spectral { values, terminated: terminated @ false, .. } = self {
+            values[0] *= HERO_COUNT as f64;
+            values[1..].fill(0.0);
+            *terminated = true;
+        }
+    }
+
+    // What the film records, spectral paths are converted through the colour matching functions
+    pub fn to_rgb(self) -> Vec3 {
+        match self {
+            Self::Rgb(colour) => colour,
+            Self::Spectral { lambdas, values, .. } => {
+                let sum = lambdas.iter().zip(values.iter()).fold(vec3!(0.0, 0.0, 0.0), |acc, (l, v)| acc + wavelength_rgb(*l) * *v);
+                sum / HERO_COUNT as f64
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::microfacet::fresnel_dielectric;
+
+    #[test]
+    fn vanishing_film_reflects_like_the_bare_surface() {
+        for cos_i in [1.0, 0.8, 0.5, 0.2] {
+            let bare = fresnel_dielectric(cos_i, 1.0 / 1.5);
+            for lambda in [450.0, 550.0, 650.0] {
+                assert!((thin_film_reflectance(cos_i, 1.0, 1.38, 1.5, 0.0, lambda) - bare).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn film_reflectance_cycles_with_thickness() {
+        // A quarter wave of the geometric mean index cancels reflection head on, as lens coatings
+        // do, and every half wave thicker the film is back to reflecting like bare glass
+        let (n_glass, lambda): (f64, f64) = (1.5, 550.0);
+        let n_film = n_glass.sqrt();
+        let quarter = lambda / (4.0 * n_film);
+        let bare = fresnel_dielectric(1.0, 1.0 / n_glass);
+        assert!(thin_film_reflectance(1.0, 1.0, n_film, n_glass, quarter, lambda) < 1e-12);
+        assert!((thin_film_reflectance(1.0, 1.0, n_film, n_glass, 2.0 * quarter, lambda) - bare).abs() < 1e-12);
+        assert!(thin_film_reflectance(1.0, 1.0, n_film, n_glass, 3.0 * quarter, lambda) < 1e-12);
+
+        // Off normal the cycle stretches with the path across the film
+        let cos_i: f64 = 0.6;
+        let cos_f = (1.0 - (1.0 - cos_i * cos_i) / (n_film * n_film)).sqrt();
+        let period = lambda / (2.0 * n_film * cos_f);
+        for thickness in [100.0, 180.0, 260.0] {
+            let r = thin_film_reflectance(cos_i, 1.0, n_film, n_glass, thickness, lambda);
+            assert!((thin_film_reflectance(cos_i, 1.0, n_film, n_glass, thickness + period, lambda) - r).abs() < 1e-12);
+        }
+
+        // A quarter wave of a film denser than the glass raises reflectance instead
+        assert!(thin_film_reflectance(1.0, 1.0, 2.0, n_glass, lambda / 8.0, lambda) > bare);
+    }
+
+    const COLOURS: [[f64; 3]; 8] = [
+        [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.8, 0.2, 0.1],
+        [0.2, 0.5, 0.9], [0.9, 0.9, 0.2], [0.1, 0.8, 0.3], [0.5, 0.5, 0.5],
+    ];
+
+    #[test]
+    fn upsampled_colours_convert_back() {
+        let white = spectral_mean(wavelength_rgb);
+        assert!((white - vec3!(1.0, 1.0, 1.0)).len() < 1e-9, "a flat spectrum comes back as {:?}", white);
+        for [r, g, b] in COLOURS {
+            let colour = vec3!(r, g, b);
+            let back = spectral_mean(|l| wavelength_rgb(l) * rgb_to_spectrum(&colour, l));
+            assert!((back - colour).len() < 0.005, "{:?} comes back as {:?}", colour, back);
+        }
+    }
+
+    #[test]
+    fn spectral_throughput_keeps_its_colour() {
+        let mut rng = Rng::with_seed(9);
+        let n = 20000;
+        for [r, g, b] in COLOURS {
+            let colour = vec3!(r, g, b);
+            let mut sum = vec3!(0.0, 0.0, 0.0);
+            for _ in 0..n {
+                let mut throughput = Throughput::spectral(&mut rng);
+                throughput.scale(&colour);
+                sum += throughput.to_rgb();
+            }
+            let mean = sum / n as f64;
+            assert!((mean - colour).len() < 0.02, "{:?} renders as {:?}", colour, mean);
+        }
+    }
+}